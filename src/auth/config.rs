@@ -0,0 +1,93 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Endpoints of the services, can be overridden to point
+//! authenticators to staging or local stand-in servers.
+
+use super::deezer::AuthDeezer;
+use super::jellyfin::AuthJellyfin;
+use super::lastfm::AuthLastFm;
+use super::listenbrainz::AuthListenBrainz;
+use super::soundcloud;
+use super::spotify;
+use super::subsonic::AuthSubsonic;
+use super::ServiceType;
+
+/// Urls used by the authenticator and API clients of a service
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::{ServiceConfig, ServiceType};
+///
+/// let config = ServiceConfig::new(ServiceType::DEEZER)
+///     .with_token_url("http://127.0.0.1:8080/oauth/access_token.php");
+///
+/// assert_eq!(config.auth_url, "https://connect.deezer.com/oauth/auth.php");
+/// assert_eq!(config.token_url, "http://127.0.0.1:8080/oauth/access_token.php");
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceConfig {
+    /// Page where user authorizes the application
+    pub auth_url: String,
+    /// Endpoint exchanging the code for token
+    pub token_url: String,
+    /// Base url of the service API
+    pub api_url: String,
+    /// Endpoint ending the session of the token, `None` when
+    /// the service can't revoke tokens
+    pub revoke_url: Option<String>,
+}
+
+impl ServiceConfig {
+    //! Service endpoints builder.
+
+    /// Create configuration with official endpoints of the service
+    pub fn new(service: ServiceType) -> ServiceConfig {
+        match service {
+            ServiceType::DEEZER => AuthDeezer::default_config(),
+            ServiceType::Spotify => spotify::default_config(),
+            ServiceType::SoundCloud => soundcloud::default_config(),
+            ServiceType::Subsonic => AuthSubsonic::default_config(),
+            ServiceType::Jellyfin => AuthJellyfin::default_config(),
+            ServiceType::LastFm => AuthLastFm::default_config(),
+            ServiceType::ListenBrainz => AuthListenBrainz::default_config(),
+        }
+    }
+
+    /// Override url of the authorization page
+    pub fn with_auth_url(mut self, url: &str) -> ServiceConfig {
+        self.auth_url = url.to_string();
+        self
+    }
+
+    /// Override url of the token endpoint
+    pub fn with_token_url(mut self, url: &str) -> ServiceConfig {
+        self.token_url = url.to_string();
+        self
+    }
+
+    /// Override base url of the API
+    pub fn with_api_url(mut self, url: &str) -> ServiceConfig {
+        self.api_url = url.to_string();
+        self
+    }
+
+    /// Override url of the revoke endpoint
+    pub fn with_revoke_url(mut self, url: &str) -> ServiceConfig {
+        self.revoke_url = Some(url.to_string());
+        self
+    }
+}
@@ -18,16 +18,51 @@
 
 use super::Authenticator;
 use super::AuthorizationStatus;
+use super::Error;
 use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::random::random_string;
+use super::redirect::{check_state, RedirectOutcome};
+use super::store::TokenStore;
+use super::token::{revoke_answer, TokenState};
+use super::uri::build_uri;
+use http::{HttpTransport, HyperTransport, Request};
 
-use std::io::Read;
-use hyper::Client;
+use std::time::{Duration, SystemTime};
+use rustc_serialize::json::Json;
+use url::form_urlencoded;
+#[cfg(feature = "serde")]
+use super::snapshot::{deserialize_authenticator, serialize_authenticator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of random bytes in the state parameter
+const STATE_LENGTH: usize = 16;
+
+/// Way how the application gets the token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Flow {
+    /// Server-side flow, code from the redirect is exchanged for token
+    /// using the application secret
+    Code,
+    /// Client-side flow, token is sent directly in the redirect uri fragment.
+    /// Used by applications which can't keep the application secret.
+    Implicit,
+}
 
 /// Store information about authorization progress and token
 pub struct AuthDeezer {
-    status: AuthorizationStatus,
-    token: String,
-    expires: String,
+    tokens: TokenState,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+    requested: Vec<Permission>,
+    state: Option<Secret>,
+    flow: Flow,
 }
 
 impl AuthDeezer {
@@ -37,131 +72,578 @@ impl AuthDeezer {
     /// Create new Deezer authentication object
     /// token will be set to empty string
     pub fn new() -> AuthDeezer {
+        AuthDeezer::with_transport(Box::new(HyperTransport::new()))
+    }
+
+    /// Create new Deezer authentication object which sends
+    /// all requests by given transport
+    pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthDeezer {
+        AuthDeezer::with_config(AuthDeezer::default_config(), transport)
+    }
+
+    /// Create new Deezer authentication object using given endpoints
+    /// and transport
+    pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>) -> AuthDeezer {
         AuthDeezer {
-            status: AuthorizationStatus::Nothing,
-            token: "".to_string(),
-            expires: "".to_string(),
+            tokens: TokenState::new(ServiceType::DEEZER),
+            transport,
+            config,
+            requested: Vec::new(),
+            state: None,
+            flow: Flow::Code,
+        }
+    }
+
+    /// Select the flow used by the authorization, `Flow::Code` is default
+    pub fn set_flow(&mut self, flow: Flow) {
+        self.flow = flow;
+    }
+
+    /// Get the flow used by the authorization
+    pub fn flow(&self) -> Flow {
+        self.flow
+    }
+
+    /// Complete client-side authorization with the redirect uri.
+    /// Token and its expiry are taken from the uri fragment
+    /// (`#access_token=...&expires=...&state=...`) without contacting
+    /// the server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::{AuthDeezer, Flow};
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission};
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// auth.set_flow(Flow::Implicit);
+    ///
+    /// let link = auth.get_authorize_link("111", "http://example.com/cb",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// assert!(link.contains("&response_type=token"));
+    /// let state = link.split("&state=").nth(1).unwrap().split('&').next().unwrap();
+    ///
+    /// let redirect = format!("http://example.com/cb#access_token=token&expires=3600&state={}",
+    ///                        state);
+    /// auth.accept_token_redirect(&redirect).unwrap();
+    ///
+    /// assert_eq!(auth.get_token().unwrap().expose(), "token");
+    /// assert!(auth.time_remaining().unwrap().unwrap().as_secs() > 3500);
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    pub fn accept_token_redirect(&mut self, response: &str) -> Result<(), Error> {
+        if self.flow != Flow::Implicit {
+            return Err(Error::InvalidState("token redirect is used only by implicit flow"))
+        }
+
+        let fragment = match response.find('#') {
+            Some(x) => &response[x+1..],
+            None => {
+                // refusal is sent in the query
+                return match RedirectOutcome::parse(response) {
+                    RedirectOutcome::Denied { reason } => {
+                        self.tokens.transition(AuthorizationStatus::Failed)?;
+                        Err(Error::OAuth(reason))
+                    }
+                    _ => Err(Error::Parse("no token in the authorization response".to_string())),
+                }
+            }
+        };
+
+        let mut token = None;
+        let mut expires = None;
+        let mut state = None;
+        for (key, value) in form_urlencoded::parse(fragment.as_bytes()) {
+            match &key[..] {
+                "access_token" => token = Some(Secret::new(value)),
+                "expires" | "expires_in" => expires = Some(value),
+                "state" => state = Some(value),
+                "error_reason" | "error" => {
+                    self.tokens.transition(AuthorizationStatus::Failed)?;
+                    return Err(Error::OAuth(value))
+                }
+                _ => {}
+            }
+        }
+
+        let token = token.ok_or_else(|| {
+            Error::Parse("no token in the authorization response".to_string())
+        })?;
+        check_state(self.state.as_ref(), state)?;
+        let expires_at = match expires {
+            Some(expires) => AuthDeezer::parse_expires(&expires)?,
+            None => None,
+        };
+
+        self.tokens.set_granted(self.requested.clone());
+        self.state = None;
+        self.save_token_with_expiry(token, expires_at)?;
+        self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+    }
+
+    /// Exchange the code for token at the token endpoint
+    fn exchange_code(&self, app_id: &str, app_secret: &Secret, code: &Secret)
+                     -> Result<(Secret, Option<SystemTime>), Error> {
+        let complete_uri = Secret::new(build_uri(&self.config.token_url, &[
+            ("app_id", app_id),
+            ("secret", app_secret.expose()),
+            ("code", code.expose()),
+        ])?);
+
+        // Get the token
+        let res = self.transport.send(&Request::get(complete_uri.expose()))?;
+        debug!("deezer token endpoint answered with status {}", res.status);
+        let extracted = AuthDeezer::extract_access_token(Secret::new(res.text()));
+        if !res.is_success() {
+            return match extracted {
+                Err(Error::OAuth(reason)) => Err(Error::OAuth(reason)),
+                _ => Err(Error::HttpStatus(res.status)),
+            }
+        }
+
+        extracted
+    }
+
+    /// Official Deezer endpoints
+    pub fn default_config() -> ServiceConfig {
+        ServiceConfig {
+            auth_url: "https://connect.deezer.com/oauth/auth.php".to_string(),
+            token_url: "https://connect.deezer.com/oauth/access_token.php".to_string(),
+            api_url: "https://api.deezer.com".to_string(),
+            revoke_url: Some("https://connect.deezer.com/logout.php".to_string()),
+        }
+    }
+
+    /// Convert `expires` value sent by Deezer (seconds from now) to absolute time.
+    /// Zero is sent for tokens with `offline_access` permission which never expire.
+    fn parse_expires(expires: &str) -> Result<Option<SystemTime>, Error> {
+        let seconds = expires.trim().parse::<u64>()
+            .map_err(|_| Error::Parse(format!("invalid expires value '{}'", expires)))?;
+
+        Ok(AuthDeezer::expiry_from_seconds(seconds))
+    }
+
+    /// Absolute time `seconds` from now, zero means never
+    fn expiry_from_seconds(seconds: u64) -> Option<SystemTime> {
+        if seconds == 0 {
+            None
+        } else {
+            Some(SystemTime::now() + Duration::from_secs(seconds))
         }
     }
 
     /// Take server response and parse it to tuple (token, expires)
-    /// or error is returned
-    fn extract_access_token(response: String) -> Result<(String, String), &'static str> {
-        let token_pattern = "access_token=";
-        let expires_pattern = "&expires=";
-        if let Some(begin) = response.find(&token_pattern) {
-            if let Some(end) = response.rfind(&expires_pattern) {
-                let token = response[(begin + token_pattern.len())..end].to_string();
-                let expires = response[(end + expires_pattern.len())..].to_string();
-
-                return Ok((token, expires))
-            };
+    /// or error is returned.
+    ///
+    /// Deezer answers either by `access_token=...&expires=...` or, when
+    /// `output=json` is requested, by `{"access_token": "...", "expires": 3600}`.
+    /// Refused codes are answered by plain `wrong code` or by JSON error
+    /// object, both are returned as `Error::OAuth`.
+    fn extract_access_token(response: Secret) -> Result<(Secret, Option<SystemTime>), Error> {
+        let body = response.expose().trim();
+
+        if body.starts_with('{') {
+            return AuthDeezer::extract_json_access_token(body)
+        }
+
+        let mut token = None;
+        let mut expires = None;
+        for (key, value) in form_urlencoded::parse(body.as_bytes()) {
+            match &key[..] {
+                "access_token" => token = Some(Secret::new(value)),
+                "expires" | "expires_in" => expires = Some(value),
+                "error_reason" | "error" => return Err(Error::OAuth(value)),
+                _ => {}
+            }
+        }
+
+        match token {
+            Some(ref token) if token.is_empty() => {
+                Err(Error::Parse("empty access token in response".to_string()))
+            }
+            Some(token) => {
+                let expires_at = match expires {
+                    Some(expires) => AuthDeezer::parse_expires(&expires)?,
+                    None => None,
+                };
+                Ok((token, expires_at))
+            }
+            // short plain text (not form or HTML page) is a message about refused code
+            None if !body.is_empty() && body.len() < 100 && !body.contains(['=', '<']) => {
+                Err(Error::OAuth(body.to_string()))
+            }
+            None => Err(Error::Parse("could not find access token part in response".to_string())),
         }
+    }
+
+    /// Parse JSON answer of the token endpoint
+    fn extract_json_access_token(body: &str) -> Result<(Secret, Option<SystemTime>), Error> {
+        let json = Json::from_str(body)
+            .map_err(|_| Error::Parse("invalid JSON in response".to_string()))?;
+
+        match json.find("error") {
+            Some(Json::Object(error)) => {
+                let reason = error.get("message").or_else(|| error.get("type"))
+                    .and_then(|reason| reason.as_string())
+                    .unwrap_or("unknown error");
+                return Err(Error::OAuth(reason.to_string()))
+            }
+            Some(Json::String(reason)) => return Err(Error::OAuth(reason.clone())),
+            _ => {}
+        }
+
+        let token = match json.find("access_token") {
+            Some(Json::String(token)) if !token.is_empty() => Secret::from(&token[..]),
+            _ => return Err(Error::Parse("could not find access token in response".to_string())),
+        };
 
-        Err("Could not find access token part in response")
+        let expires_at = match json.find("expires").or_else(|| json.find("expires_in")) {
+            Some(Json::String(expires)) => AuthDeezer::parse_expires(expires)?,
+            Some(Json::Null) | None => None,
+            Some(expires) => {
+                let seconds = expires.as_u64()
+                    .ok_or_else(|| Error::Parse("invalid expires value".to_string()))?;
+                AuthDeezer::expiry_from_seconds(seconds)
+            }
+        };
+
+        Ok((token, expires_at))
+    }
+}
+
+impl Default for AuthDeezer {
+    fn default() -> AuthDeezer {
+        AuthDeezer::new()
+    }
+}
+
+/// Authenticator is written as `export_snapshot(false)`, the token
+/// doesn't survive a serde round-trip. Serialize `export_snapshot(true)`
+/// to move the session with its token.
+#[cfg(feature = "serde")]
+impl Serialize for AuthDeezer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_authenticator(self, serializer)
+    }
+}
+
+/// Authenticator using official endpoints is restored from the snapshot
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AuthDeezer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<AuthDeezer, D::Error> {
+        deserialize_authenticator(deserializer, |_| Some(AuthDeezer::new()))
     }
 }
 
 impl Authenticator for AuthDeezer {
     
-    /// Get status of ongoing authentication
-    fn status(&self) -> &AuthorizationStatus {
-        &self.status
+    /// Get status of ongoing authentication.
+    /// Once the token runs out the status is `Expired`, observers learn
+    /// about the expiry when the status is read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// let expired = SystemTime::now() - Duration::from_secs(1);
+    /// auth.save_token_with_expiry(Secret::from("token"), Some(expired)).unwrap();
+    ///
+    /// assert!(!auth.is_token_valid());
+    /// assert_eq!(auth.time_remaining().unwrap(), Some(Duration::from_secs(0)));
+    /// assert_eq!(auth.status(), AuthorizationStatus::Expired);
+    /// ```
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    /// Register callback which receives every status change
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    /// use music_streamer::auth::AuthorizationStatus::*;
+    ///
+    /// let changes = Arc::new(Mutex::new(Vec::new()));
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, "access_token=token&expires=3600"));
+    ///
+    /// let mut auth = AuthDeezer::with_transport(Box::new(mock));
+    /// let log = changes.clone();
+    /// auth.on_status_change(Box::new(move |from, to| log.lock().unwrap().push((from, to))));
+    ///
+    /// // code can't be exchanged before the user authorized the application
+    /// match auth.authenticate_application("111", &Secret::from("s"), &Secret::from("c")) {
+    ///     Err(Error::InvalidTransition(Nothing, CodeReceived)) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    ///
+    /// let link = auth.get_authorize_link("111", "http://example.com",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// let state = link.split("&state=").nth(1).unwrap();
+    /// let code = auth.parse_response_code(&format!("http://example.com?code=c&state={}", state))
+    ///     .unwrap();
+    /// auth.authenticate_application("111", &Secret::from("s"), &code).unwrap();
+    ///
+    /// assert_eq!(*changes.lock().unwrap(), vec![
+    ///     (Nothing, UserAuthentication),
+    ///     (UserAuthentication, CodeReceived),
+    ///     (CodeReceived, TokenAcquired),
+    ///     (TokenAcquired, AuthorizationCompleted),
+    /// ]);
+    /// ```
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get endpoints used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
     }
     
     /// Create uri for user authentication in form:
     ///
-    /// https://connect.deezer.com/oauth/auth.php?app_id=YOUR_APP_ID&redirect_uri=YOUR_REDIRECT_URI&perms=basic_access,email
+    /// https://connect.deezer.com/oauth/auth.php?app_id=YOUR_APP_ID&redirect_uri=YOUR_REDIRECT_URI&perms=basic_access,email&state=RANDOM_STATE
+    ///
+    /// All parameters are percent-encoded. New random state is generated
+    /// for every link. `response_type=token` is added for the implicit flow.
     ///
     /// # Examples
     ///
     /// ```
     /// use music_streamer::auth::deezer::AuthDeezer;
-    /// use music_streamer::auth::AuthMethods;
+    /// use music_streamer::auth::{Authenticator, Permission};
     ///
     /// let mut auth = AuthDeezer::new();
     ///
-    /// let link = auth.get_authorize_link("111", "http://example.com");
-    /// assert_eq!(link, "https://connect.deezer.com/oauth/auth.php?app_id=111\
-    ///                   &redirect_uri=http://example.com&perms=basic_access");
-    /// ```
-    fn get_authorize_link(&mut self, app_id: &str, redirect_uri: &str, permissions: &[Permission]) -> String {
-        let mut perm_string = "&perms=".to_string();
-
-        for perm in permissions {
-            perm_string = perm_string + match perm {
-                &Permission::BasicAccess => "basic_access",
-                &Permission::Email => "email",
-                &Permission::OfflineAccess => "offline_access",
-                &Permission::ManageLibrary => "manage_library",
-                &Permission::ManageCommunity => "manage_community",
-                &Permission::DeleteLibrary => "delete_library",
-                &Permission::ListeningHistory => "listening_history",
-            }
+    /// let link = auth.get_authorize_link("111", "http://example.com",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// assert!(link.starts_with("https://connect.deezer.com/oauth/auth.php?app_id=111\
+    ///                           &redirect_uri=http%3A%2F%2Fexample.com&perms=basic_access\
+    ///                           &state="));
+    ///
+    /// // permissions are separated by commas and sent only once
+    /// let link = auth.get_authorize_link("111", "http://example.com",
+    ///                                    &[Permission::BasicAccess, Permission::Email,
+    ///                                      Permission::BasicAccess]).unwrap();
+    /// assert!(link.contains("&perms=basic_access%2Cemail&"));
+    ///
+    /// // query, spaces and non-ASCII characters of redirect uri survive
+    /// let link = auth.get_authorize_link("111", "http://example.com/cb?user=a b&next=/é#top",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// assert!(link.starts_with("https://connect.deezer.com/oauth/auth.php?app_id=111\
+    ///                           &redirect_uri=http%3A%2F%2Fexample.com%2Fcb%3Fuser%3Da+b%26next%3D%2F%C3%A9%23top\
+    ///                           &perms=basic_access&state="));
+    ///
+    /// // application id is encoded as well
+    /// let link = auth.get_authorize_link("1&x=2", "http://localhost:8080/",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// assert!(link.starts_with("https://connect.deezer.com/oauth/auth.php?app_id=1%26x%3D2\
+    ///                           &redirect_uri=http%3A%2F%2Flocalhost%3A8080%2F&perms=basic_access\
+    ///                           &state="));
+    /// ```
+    fn get_authorize_link(&mut self, app_id: &str, redirect_uri: &str, permissions: &[Permission])
+                          -> Result<String, Error> {
+        let state = Secret::new(random_string(STATE_LENGTH)?);
+        let perms = Permission::join(permissions);
+        let mut params = vec![
+            ("app_id", app_id),
+            ("redirect_uri", redirect_uri),
+            ("perms", &perms[..]),
+        ];
+        if self.flow == Flow::Implicit {
+            params.push(("response_type", "token"));
         }
+        params.push(("state", state.expose()));
 
-        let base_uri = "https://connect.deezer.com/oauth/auth.php?app_id=".to_string();
-        let complete_uri = base_uri + app_id + "&redirect_uri=" + redirect_uri + &perm_string;
-        self.status = AuthorizationStatus::UserAuthentication;
-        complete_uri
+        let complete_uri = build_uri(&self.config.auth_url, &params)?;
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+        self.requested = Permission::dedup(permissions);
+        self.state = Some(state);
+        Ok(complete_uri)
     }
 
 
-    /// Get code from authorization response uri
+    /// Get code from authorization response uri.
+    /// When user refused the access Deezer sends `error_reason` instead
+    /// of the code, it is returned as `Error::OAuth`.
+    /// Code is accepted only with the state sent in the last authorize link,
+    /// the status moves to `CodeReceived`. Refused authorization moves it
+    /// to `Failed`.
     ///
     /// # Examples
     ///
     /// ```
     /// use music_streamer::auth::deezer::AuthDeezer;
-    /// use music_streamer::auth::AuthMethods;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission};
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// assert_eq!(auth.status(), AuthorizationStatus::Nothing);
+    /// let link = auth.get_authorize_link("111", "http://example.com/test_path/",
+    ///                                    &[Permission::BasicAccess]).unwrap();
+    /// let state = link.split("&state=").nth(1).unwrap();
     ///
-    /// let auth = AuthDeezer::new();
+    /// let test = format!("http://example.com/test_path/?code=fre54bf0a48d1bf566f24c2289ce06d1\
+    ///                     &state={}", state);
+    /// let result = auth.parse_response_code(&test).unwrap();
     ///
-    /// let test = "http://example.com/test_path/?code=fre54bf0a48d1bf566f24c2289ce06d1";
-    /// let result = auth.parse_reponse_code(test);
+    /// assert_eq!(result.expose(), "fre54bf0a48d1bf566f24c2289ce06d1");
+    /// assert_eq!(auth.status(), AuthorizationStatus::CodeReceived);
     ///
-    /// assert_eq!(result, Some("fre54bf0a48d1bf566f24c2289ce06d1".to_string()));
+    /// let forged = "http://example.com/test_path/?code=fre54bf0a48d1bf566f24c2289ce06d1\
+    ///               &state=forged";
+    /// match auth.parse_response_code(forged) {
+    ///     Err(Error::StateMismatch) => {}
+    ///     _ => panic!("code with wrong state must be rejected"),
+    /// }
+    ///
+    /// let denied = "http://example.com/test_path/?error_reason=user_denied";
+    /// match auth.parse_response_code(denied) {
+    ///     Err(Error::OAuth(reason)) => assert_eq!(reason, "user_denied"),
+    ///     _ => panic!("denied authorization must be reported"),
+    /// }
+    /// assert_eq!(auth.status(), AuthorizationStatus::Failed);
     /// ```
-    fn parse_response_code(&self, response: &str) -> Option<String> {
-        let option = response.to_string().rfind("?code=");
-
-        if let Some(x) = option {
-            Some(response[x+6..].to_string())
-        } else {
-            None
+    fn parse_response_code(&mut self, response: &str) -> Result<Secret, Error> {
+        match RedirectOutcome::parse(response) {
+            RedirectOutcome::Granted { code, state } => {
+                check_state(self.state.as_ref(), state)?;
+                self.tokens.transition(AuthorizationStatus::CodeReceived)?;
+                Ok(code)
+            }
+            RedirectOutcome::Denied { reason } => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            RedirectOutcome::Malformed(msg) => Err(Error::Parse(msg)),
         }
     }
 
     /// Authenticate application with code get from get_authorization_response link.
     /// This will connect to deezer and retrieve token for future communication.
-    fn authenticate_application(&mut self, app_id: &str, app_secret: &str,
-                               code: &str) -> Result<(), &str> {
-        let base_uri = "https://connect.deezer.com/oauth/access_token.php?app_id=".to_string();
-        let complete_uri = base_uri + app_id + "&secret=" + app_secret + "&code=" + code;
+    /// Authorization has to be started by `get_authorize_link` first,
+    /// failed exchange moves the status to `Failed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, Permission, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, "access_token=token&expires=3600"));
+    ///
+    /// let mut auth = AuthDeezer::with_transport(Box::new(mock.clone()));
+    /// auth.get_authorize_link("111", "http://example.com", &[Permission::Email]).unwrap();
+    /// auth.authenticate_application("111", &Secret::from("secret"), &Secret::from("co&de"))
+    ///     .unwrap();
+    ///
+    /// assert_eq!(auth.get_token().unwrap().expose(), "token");
+    /// assert!(auth.has_permission(Permission::Email));
+    /// assert!(!auth.has_permission(Permission::ManageLibrary));
+    /// assert_eq!(mock.requests()[0].url, "https://connect.deezer.com/oauth/access_token.php\
+    ///                                     ?app_id=111&secret=secret&code=co%26de");
+    /// ```
+    ///
+    /// Both plain and JSON answers are understood:
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// // (status, body, expected token, expected seconds remaining)
+    /// let tokens = vec![
+    ///     (200, "access_token=abc&expires=3600", "abc", Some(3600)),
+    ///     (200, "expires=3600&access_token=abc", "abc", Some(3600)),
+    ///     (200, "access_token=abc&expires=0", "abc", None),
+    ///     (200, "access_token=a%2Bb&foo=bar&expires=60&baz=1\n", "a+b", Some(60)),
+    ///     (200, r#"{"access_token":"abc","expires":3600}"#, "abc", Some(3600)),
+    ///     (200, r#"{"expires":"60","access_token":"abc","extra":[1]}"#, "abc", Some(60)),
+    ///     (200, r#"{"access_token":"abc","expires":0}"#, "abc", None),
+    /// ];
+    /// for (status, body, token, remaining) in tokens {
+    ///     let mock = MockTransport::new();
+    ///     mock.push_response(Response::new(status, body));
+    ///     let mut auth = AuthDeezer::with_transport(Box::new(mock));
+    ///     auth.get_authorize_link("111", "http://example.com", &[Permission::Email]).unwrap();
+    ///     auth.authenticate_application("111", &Secret::from("s"), &Secret::from("c"))
+    ///         .expect(body);
+    ///
+    ///     assert_eq!(auth.get_token().unwrap().expose(), token, "{}", body);
+    ///     let left = auth.time_remaining().unwrap().map(|left| (left.as_secs() + 5) / 10 * 10);
+    ///     assert_eq!(left, remaining, "{}", body);
+    /// }
+    ///
+    /// // (status, body, expected refusal reason)
+    /// let refused = vec![
+    ///     (200, "wrong code", Some("wrong code")),
+    ///     (200, "error_reason=user_denied", Some("user_denied")),
+    ///     (400, r#"{"error":{"type":"OAuthException","message":"Invalid code.","code":4}}"#,
+    ///      Some("Invalid code.")),
+    ///     (200, r#"{"error":{"type":"OAuthException"}}"#, Some("OAuthException")),
+    ///     (200, "access_token=&expires=3600", None),
+    ///     (200, "access_token=abc&expires=soon", None),
+    ///     (200, r#"{"access_token":42}"#, None),
+    ///     (200, "{not json", None),
+    ///     (200, "", None),
+    /// ];
+    /// for (status, body, reason) in refused {
+    ///     let mock = MockTransport::new();
+    ///     mock.push_response(Response::new(status, body));
+    ///     let mut auth = AuthDeezer::with_transport(Box::new(mock));
+    ///     auth.get_authorize_link("111", "http://example.com", &[Permission::Email]).unwrap();
+    ///     match (auth.authenticate_application("111", &Secret::from("s"), &Secret::from("c")),
+    ///            reason) {
+    ///         (Err(Error::OAuth(ref got)), Some(expected)) => assert_eq!(got, expected),
+    ///         (Err(Error::Parse(_)), None) => {}
+    ///         (other, _) => panic!("{}: unexpected {:?}", body, other),
+    ///     }
+    ///     assert!(auth.get_token().is_err());
+    ///     assert_eq!(auth.status(), AuthorizationStatus::Failed);
+    /// }
+    ///
+    /// // server errors without explanation keep their status
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(503, "<html>Service Unavailable</html>"));
+    /// let mut auth = AuthDeezer::with_transport(Box::new(mock));
+    /// auth.get_authorize_link("111", "http://example.com", &[Permission::Email]).unwrap();
+    /// match auth.authenticate_application("111", &Secret::from("s"), &Secret::from("c")) {
+    ///     Err(Error::HttpStatus(503)) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    fn authenticate_application(&mut self, app_id: &str, app_secret: &Secret,
+                               code: &Secret) -> Result<(), Error> {
+        if self.flow != Flow::Code {
+            return Err(Error::InvalidState("implicit flow doesn't exchange code for token"))
+        }
+        // code may be handed over without parse_response_code
+        self.tokens.transition(AuthorizationStatus::CodeReceived)?;
 
-        // Get the token
-        let client = Client::new();
-        // Send get to the server
-        if let Ok(mut res) = client.get(&complete_uri).send() {
-            let mut body = String::new();
-            let ret = res.read_to_string(&mut body);
-
-            if ret.is_err() {
-                return Err("Can't read the response. Something is really wrong.")
+        let (token, expires_at) = match self.exchange_code(app_id, app_secret, code) {
+            Ok(token) => token,
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                return Err(err)
             }
+        };
 
-            println!("response: {}", body);
-            let (token, expires) = try!(AuthDeezer::extract_access_token(body));
-            self.save_token(token);
-            self.expires = expires;
-
-            // retrieve the token
-            self.status = AuthorizationStatus::AuthorizationCompleted;
-        } else {
-            return Err("Can't send request to the deezer server")
-        }
+        // Deezer lets user accept or refuse all requested permissions at once
+        self.tokens.set_granted(self.requested.clone());
+        self.state = None;
+        self.save_token_with_expiry(token, expires_at)?;
 
-        Ok(())
+        // retrieve the token
+        self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
     }
 
     /// Save token to authentication object
@@ -172,25 +654,215 @@ impl Authenticator for AuthDeezer {
     ///
     /// ```
     /// use music_streamer::auth::deezer::AuthDeezer;
-    /// use music_streamer::auth::AuthMethods;
+    /// use music_streamer::auth::{Authenticator, Secret};
     /// 
-    /// let mut token = "token".to_string();
+    /// let token = Secret::from("token");
     /// let mut auth = AuthDeezer::new();
-    /// assert_eq!(auth.save_token(token), true);
+    /// assert!(auth.save_token(token).is_ok());
     /// 
-    /// let load_token = auth.get_token();
-    /// assert_eq!(load_token, "token");
+    /// let load_token = auth.get_token().unwrap();
+    /// assert_eq!(load_token.expose(), "token");
     /// ```
     ///
-    fn save_token(&mut self, token: String) {
-        self.token = token;
-        self.status = AuthorizationStatus::TokenAquired;
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save token which stops being valid at `expires_at`.
+    /// `None` is used for tokens which never expire.
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        self.tokens.save(token, expires_at)
     }
     
     /// Get active user token
     /// 
     /// DO NOT STORE THE TOKEN ELSEWHERE
-    fn get_token(&self) -> String {
-        self.token.to_string()
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get()
+    }
+
+    /// Check if there is a token which didn't expire yet
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// Get time when the token expires, `None` if it never expires
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.tokens.expires_at()
+    }
+
+    /// Get how long the token will be valid, `None` if it never expires
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.tokens.time_remaining()
+    }
+
+    /// Get permissions granted by the user to the application
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    /// Attach store and restore token of the account from it.
+    /// Expired tokens are not restored. Restored token abandons
+    /// the authorization in progress, the status starts over from
+    /// `Nothing` and ends `AuthorizationCompleted`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+    /// use music_streamer::auth::store::MemoryStore;
+    ///
+    /// let store = MemoryStore::new();
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// assert_eq!(auth.attach_store(Box::new(store.clone()), "user").unwrap(), false);
+    /// auth.save_token(Secret::from("token")).unwrap();
+    ///
+    /// // after restart
+    /// let mut auth = AuthDeezer::new();
+    /// assert_eq!(auth.attach_store(Box::new(store), "user").unwrap(), true);
+    /// assert_eq!(auth.get_token().unwrap().expose(), "token");
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.state = None;
+        self.tokens.attach_store(store, account)
+    }
+
+    /// Forget the token and delete it from the attached store
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+    /// use music_streamer::auth::store::MemoryStore;
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut auth = AuthDeezer::new();
+    /// auth.attach_store(Box::new(store.clone()), "user").unwrap();
+    /// auth.save_token(Secret::from("token")).unwrap();
+    ///
+    /// auth.logout().unwrap();
+    /// assert!(auth.get_token().is_err());
+    /// assert_eq!(auth.status(), AuthorizationStatus::Nothing);
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// assert_eq!(auth.attach_store(Box::new(store), "user").unwrap(), false);
+    /// ```
+    fn logout(&mut self) -> Result<(), Error> {
+        self.state = None;
+        self.tokens.logout()
+    }
+
+    /// Export the session with granted permissions and expiry.
+    /// The token is included only when `include_secrets` is true,
+    /// the snapshot is then as sensitive as the token itself.
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    /// Restore the session from the snapshot, the token is saved
+    /// to the attached store.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission, Secret};
+    /// use music_streamer::auth::{ServiceType, SessionSnapshot};
+    ///
+    /// let snapshot = SessionSnapshot {
+    ///     version: 1,
+    ///     service: ServiceType::DEEZER,
+    ///     status: AuthorizationStatus::AuthorizationCompleted,
+    ///     permissions: vec![Permission::Email],
+    ///     expires_at: None,
+    ///     token: Some(Secret::from("token")),
+    ///     refresh_token: None,
+    /// };
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// auth.import_snapshot(&snapshot).unwrap();
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert!(auth.has_permission(Permission::Email));
+    /// assert_eq!(auth.export_snapshot(true), snapshot);
+    /// assert_eq!(auth.export_snapshot(false).token, None);
+    ///
+    /// // snapshot of newer library is refused
+    /// let newer = SessionSnapshot { version: 2, ..snapshot };
+    /// assert!(AuthDeezer::new().import_snapshot(&newer).is_err());
+    /// ```
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        self.tokens.import(snapshot)?;
+        self.state = None;
+        Ok(())
+    }
+
+    /// End the session at Deezer and forget the token.
+    /// Token is forgotten even when Deezer can't be reached,
+    /// the error is returned afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Secret};
+    /// use music_streamer::auth::{ServiceConfig, ServiceType};
+    /// use music_streamer::auth::store::{MemoryStore, TokenStore};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, "true"));
+    /// let config = ServiceConfig::new(ServiceType::DEEZER)
+    ///     .with_revoke_url("http://127.0.0.1:8080/logout.php");
+    /// let store = MemoryStore::new();
+    ///
+    /// let mut auth = AuthDeezer::with_config(config.clone(), Box::new(mock.clone()));
+    /// auth.attach_store(Box::new(store.clone()), "user").unwrap();
+    /// auth.save_token(Secret::from("to&ken")).unwrap();
+    ///
+    /// auth.revoke().unwrap();
+    /// assert_eq!(mock.requests()[0].url, "http://127.0.0.1:8080/logout.php?access_token=to%26ken");
+    /// assert!(auth.get_token().is_err());
+    /// assert!(auth.expires_at().is_err());
+    /// assert!(store.load(ServiceType::DEEZER, "user").unwrap().is_none());
+    /// assert_eq!(auth.status(), AuthorizationStatus::Revoked);
+    ///
+    /// // revoked session can't take a token, start over first
+    /// assert!(auth.save_token(Secret::from("token")).is_err());
+    /// auth.logout().unwrap();
+    ///
+    /// // token is forgotten even when the server refuses
+    /// mock.push_response(Response::new(500, ""));
+    /// auth.save_token(Secret::from("token")).unwrap();
+    /// match auth.revoke() {
+    ///     Err(Error::HttpStatus(500)) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert!(auth.get_token().is_err());
+    ///
+    /// // nothing is sent without token
+    /// auth.revoke().unwrap();
+    /// assert_eq!(mock.requests().len(), 2);
+    /// ```
+    fn revoke(&mut self) -> Result<(), Error> {
+        if !self.tokens.has_token() {
+            return self.logout()
+        }
+
+        let answer = match self.config.revoke_url {
+            Some(ref url) => {
+                let token = self.tokens.get()?.expose();
+                let uri = Secret::new(build_uri(url, &[("access_token", token)])?);
+                revoke_answer(self.transport.send(&Request::get(uri.expose())))
+            }
+            None => Ok(()),
+        };
+
+        self.state = None;
+        self.tokens.revoke(answer)
     }
 }
@@ -0,0 +1,79 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Error type shared by all authenticators.
+
+use std::error;
+use std::fmt;
+use std::io;
+
+use hyper;
+
+use super::AuthorizationStatus;
+
+/// Everything what can go wrong during authorization and authentication
+#[derive(Debug)]
+pub enum Error {
+    /// Request couldn't be sent or response couldn't be read
+    Transport(String),
+    /// Server answered with unexpected HTTP status code
+    HttpStatus(u16),
+    /// Server response or redirect uri couldn't be understood
+    Parse(String),
+    /// Service refused the authorization, carries the reason
+    /// sent by the service (for example Deezer's `error_reason`)
+    OAuth(String),
+    /// Method was called when the authorization is not in the right state
+    InvalidState(&'static str),
+    /// Token couldn't be saved to or loaded from the token store
+    Storage(String),
+    /// `state` of the authorization response doesn't match the one sent
+    /// in the authorize link, the response could be forged
+    StateMismatch,
+    /// Operation would move the authorization from the first
+    /// status to the second one which is not allowed
+    InvalidTransition(AuthorizationStatus, AuthorizationStatus),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Transport(ref msg) => write!(f, "transport error: {}", msg),
+            Error::HttpStatus(code) => write!(f, "unexpected HTTP status {}", code),
+            Error::Parse(ref msg) => write!(f, "can't parse response: {}", msg),
+            Error::OAuth(ref reason) => write!(f, "authorization refused: {}", reason),
+            Error::InvalidState(msg) => write!(f, "invalid authorization state: {}", msg),
+            Error::Storage(ref msg) => write!(f, "token store error: {}", msg),
+            Error::StateMismatch => write!(f, "authorization state doesn't match"),
+            Error::InvalidTransition(from, to) => {
+                write!(f, "authorization can't go from {:?} to {:?}", from, to)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<hyper::Error> for Error {
+    fn from(err: hyper::Error) -> Error {
+        Error::Transport(err.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Transport(err.to_string())
+    }
+}
@@ -0,0 +1,580 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Jellyfin implementation of authorization and authentication trait.
+//!
+//! The user logs in by name and password (`/Users/AuthenticateByName`)
+//! or the administrator issues an API key. Every request identifies
+//! the client and the device by `Authorization: MediaBrowser ...` header,
+//! Jellyfin keeps one session per device id and the token works only
+//! with the device id it was issued to. The application has to keep
+//! its device id between restarts and set it by `set_device` before
+//! a token is restored.
+//!
+//! # Examples
+//!
+//! ```
+//! use music_streamer::auth::jellyfin::AuthJellyfin;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+//! use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"User":{"Id":"4f2a","Name":"alice"},
+//!     "AccessToken":"token","ServerId":"s"}"#));
+//! let config = ServiceConfig::new(ServiceType::Jellyfin)
+//!     .with_api_url("http://127.0.0.1:8096/jellyfin/");
+//! let mut auth = AuthJellyfin::with_config(config, Box::new(mock.clone())).unwrap();
+//! auth.set_device("Living room", "device-1");
+//! auth.login("alice", &Secret::from("sesame")).unwrap();
+//!
+//! assert_eq!(auth.get_token().unwrap().expose(), "token");
+//! assert_eq!(auth.user_id(), Some("4f2a"));
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//!
+//! let request = &mock.requests()[0];
+//! assert_eq!(request.url, "http://127.0.0.1:8096/jellyfin/Users/AuthenticateByName");
+//! assert_eq!(String::from_utf8(request.body.clone()).unwrap(),
+//!            r#"{"Pw":"sesame","Username":"alice"}"#);
+//! ```
+
+use super::Authenticator;
+use super::AuthorizationStatus;
+use super::CredentialAuthenticator;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::random::random_string;
+use super::store::TokenStore;
+use super::token::{revoke_answer, TokenState};
+use super::wipe_json;
+use http::{HttpTransport, HyperTransport, Request, Response};
+
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+use rustc_serialize::json::Json;
+
+/// Number of random bytes in the generated device id
+const DEVICE_ID_LENGTH: usize = 16;
+
+/// Store information about authorization progress and token
+pub struct AuthJellyfin {
+    tokens: TokenState,
+    user_id: Option<String>,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+    client: String,
+    device: String,
+    device_id: String,
+    /// Device id was given by the application, not generated
+    device_kept: bool,
+}
+
+impl AuthJellyfin {
+    //! Authentication object for Jellyfin servers.
+
+    /// Create new Jellyfin authentication object for the server
+    /// at `http://localhost:8096`
+    pub fn new() -> Result<AuthJellyfin, Error> {
+        AuthJellyfin::with_transport(Box::new(HyperTransport::new()))
+    }
+
+    /// Create new Jellyfin authentication object which sends
+    /// all requests by given transport
+    pub fn with_transport(transport: Box<dyn HttpTransport>) -> Result<AuthJellyfin, Error> {
+        AuthJellyfin::with_config(AuthJellyfin::default_config(), transport)
+    }
+
+    /// Create new Jellyfin authentication object for the server
+    /// at `config.api_url` using given transport.
+    /// Random device id is generated, replace it by `set_device`
+    /// with the one kept from the last run. Fails when the system
+    /// can't give random numbers for the device id.
+    pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>)
+                       -> Result<AuthJellyfin, Error> {
+        Ok(AuthJellyfin {
+            tokens: TokenState::permanent(ServiceType::Jellyfin).with_verification(),
+            user_id: None,
+            transport,
+            config,
+            client: "music_streamer".to_string(),
+            device: "music_streamer".to_string(),
+            device_id: random_string(DEVICE_ID_LENGTH)?,
+            device_kept: false,
+        })
+    }
+
+    /// Jellyfin servers are self-hosted, default is local server.
+    /// Only `api_url` is used.
+    pub fn default_config() -> ServiceConfig {
+        ServiceConfig {
+            auth_url: String::new(),
+            token_url: String::new(),
+            api_url: "http://localhost:8096".to_string(),
+            revoke_url: None,
+        }
+    }
+
+    /// Set client name shown in the Jellyfin dashboard, default is `music_streamer`
+    pub fn set_client(&mut self, client: &str) {
+        self.client = client.to_string();
+    }
+
+    /// Set device name and id, sessions of the server belong to the device id.
+    /// Has to be called before a token is restored.
+    pub fn set_device(&mut self, name: &str, id: &str) {
+        self.device = name.to_string();
+        self.device_id = id.to_string();
+        self.device_kept = true;
+    }
+
+    /// Get id of the device
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+
+    /// Get id of the logged user, `None` when logged in by API key
+    /// or when the restored token wasn't verified yet
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_ref().map(|id| &id[..])
+    }
+
+    /// Get value of `Authorization` header sent with every request,
+    /// it contains the token once the user is logged in
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::jellyfin::AuthJellyfin;
+    /// use music_streamer::auth::{Authenticator, Secret};
+    ///
+    /// let mut auth = AuthJellyfin::new().unwrap();
+    /// auth.set_device("My \"phone\"", "1234");
+    /// auth.save_token(Secret::from("token")).unwrap();
+    /// assert_eq!(auth.authorization_header().expose(),
+    ///            format!("MediaBrowser Client=\"music_streamer\", Device=\"My phone\", \
+    ///                     DeviceId=\"1234\", Version=\"{}\", Token=\"token\"",
+    ///                    env!("CARGO_PKG_VERSION")));
+    /// ```
+    pub fn authorization_header(&self) -> Secret {
+        let token = self.tokens.get().ok().cloned().unwrap_or_default();
+        AuthJellyfin::header_with_token(&self.client, &self.device, &self.device_id, &token)
+    }
+
+    /// Build `Authorization` header, quotes would break the header
+    /// so they are left out of the values
+    fn header_with_token(client: &str, device: &str, device_id: &str, token: &Secret) -> Secret {
+        let quoted = |value: &str| value.replace('"', "");
+        let mut header = format!("MediaBrowser Client=\"{}\", Device=\"{}\", DeviceId=\"{}\", \
+                                  Version=\"{}\"",
+                                 quoted(client), quoted(device), quoted(device_id),
+                                 env!("CARGO_PKG_VERSION"));
+        if !token.is_empty() {
+            header.push_str(&format!(", Token=\"{}\"", quoted(token.expose())));
+        }
+        Secret::new(header)
+    }
+
+    /// Full url of the server endpoint
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}{}", self.config.api_url.trim_end_matches('/'), path)
+    }
+
+    /// Check the token by `/System/Info` which needs authentication
+    fn check(&self, token: &Secret) -> Result<(), Error> {
+        let header = AuthJellyfin::header_with_token(&self.client, &self.device,
+                                                     &self.device_id, token);
+        let request = Request::get(&self.endpoint("/System/Info"))
+            .header("Authorization", header.expose());
+
+        let res = self.transport.send(&request)?;
+        debug!("jellyfin system info answered with status {}", res.status);
+        AuthJellyfin::check_status(&res)
+    }
+
+    /// Find the user of the token by `/Users/Me`, `None` for API keys
+    /// which belong to no user. Refused token is returned as `Error::OAuth`.
+    fn current_user(&self, token: &Secret) -> Result<Option<String>, Error> {
+        let header = AuthJellyfin::header_with_token(&self.client, &self.device,
+                                                     &self.device_id, token);
+        let request = Request::get(&self.endpoint("/Users/Me"))
+            .header("Authorization", header.expose());
+
+        let res = self.transport.send(&request)?;
+        debug!("jellyfin current user answered with status {}", res.status);
+        if res.status != 401 && !res.is_success() {
+            // API key is not a user, check it is accepted at all
+            return self.check(token).map(|()| None)
+        }
+        AuthJellyfin::check_status(&res)?;
+
+        let json = Json::from_str(&res.text())
+            .map_err(|err| Error::Parse(format!("invalid user response: {}", err)))?;
+        match json.find("Id").and_then(|id| id.as_string()) {
+            Some(id) if !id.is_empty() => Ok(Some(id.to_string())),
+            _ => Err(Error::Parse("no id in the user response".to_string())),
+        }
+    }
+
+    /// Tokens work only with the device id they were issued to,
+    /// the generated one can't be it
+    fn check_device(&self) -> Result<(), Error> {
+        if !self.device_kept {
+            return Err(Error::InvalidState("set the device id the token was issued to \
+                                            by set_device first"))
+        }
+        Ok(())
+    }
+
+    /// Refused credentials are answered by 401
+    fn check_status(res: &Response) -> Result<(), Error> {
+        match res.status {
+            401 => Err(Error::OAuth("invalid credentials".to_string())),
+            _ if !res.is_success() => Err(Error::HttpStatus(res.status)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Complete the login with the checked token, failure moves
+    /// the status to `Failed`
+    fn finish_login(&mut self, result: Result<(Secret, Option<String>), Error>)
+                    -> Result<(), Error> {
+        match result {
+            Ok((token, user_id)) => {
+                self.save_token(token)?;
+                self.user_id = user_id;
+                self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+            }
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Send credentials to `/Users/AuthenticateByName`
+    fn authenticate_by_name(&self, username: &str, password: &Secret)
+                            -> Result<(Secret, Option<String>), Error> {
+        let mut body = BTreeMap::new();
+        body.insert("Username".to_string(), Json::String(username.to_string()));
+        body.insert("Pw".to_string(), Json::String(password.expose().to_string()));
+        let mut json = Json::Object(body);
+        let body = Secret::new(json.to_string());
+        wipe_json(&mut json);
+
+        let header = AuthJellyfin::header_with_token(&self.client, &self.device,
+                                                     &self.device_id, &Secret::default());
+        let request = Request::post(&self.endpoint("/Users/AuthenticateByName"))
+            .header("Content-Type", "application/json")
+            .header("Authorization", header.expose())
+            .body(body.expose().as_bytes().to_vec());
+
+        let res = self.transport.send(&request)?;
+        debug!("jellyfin authentication answered with status {}", res.status);
+        AuthJellyfin::check_status(&res)?;
+
+        let answer = Secret::new(res.text());
+        let mut json = Json::from_str(answer.expose())
+            .map_err(|err| Error::Parse(format!("invalid authentication response: {}", err)))?;
+        let token = json.find("AccessToken").and_then(|token| token.as_string())
+            .filter(|token| !token.is_empty())
+            .map(Secret::from);
+        let user_id = json.find_path(&["User", "Id"])
+            .and_then(|id| id.as_string())
+            .map(str::to_string);
+        wipe_json(&mut json);
+
+        match token {
+            Some(token) => Ok((token, user_id)),
+            None => Err(Error::Parse("no token in the authentication response".to_string())),
+        }
+    }
+
+}
+
+impl Authenticator for AuthJellyfin {
+
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get server used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// Jellyfin has no authorization page, use `login`
+    fn get_authorize_link(&mut self, _app_id: &str, _redirect_uri: &str,
+                          _permissions: &[Permission]) -> Result<String, Error> {
+        Err(Error::InvalidState("jellyfin logs in by user name and password or API key"))
+    }
+
+    /// Jellyfin has no authorization page, use `login`
+    fn parse_response_code(&mut self, _response: &str) -> Result<Secret, Error> {
+        Err(Error::InvalidState("jellyfin logs in by user name and password or API key"))
+    }
+
+    /// Jellyfin has no authorization codes, use `login`
+    fn authenticate_application(&mut self, _app_id: &str, _app_secret: &Secret,
+                                _code: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("jellyfin logs in by user name and password or API key"))
+    }
+
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save token, Jellyfin tokens never expire
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        self.tokens.save(token, expires_at)?;
+        self.user_id = None;
+        Ok(())
+    }
+
+    /// Get access token or API key
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get().map_err(|_| Error::InvalidState("user is not logged in"))
+    }
+
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// Jellyfin tokens never expire
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Jellyfin tokens never expire
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Jellyfin has no permissions, access is given by the user policy on the server
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    /// Attach store and restore token of the account from it.
+    /// Device id has to be set by `set_device` first. Restored token
+    /// stays `TokenAcquired` until `verify` checks it and finds the user.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::jellyfin::AuthJellyfin;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::auth::store::MemoryStore;
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut auth = AuthJellyfin::new().unwrap();
+    /// auth.set_device("Living room", "device-1");
+    /// auth.attach_store(Box::new(store.clone()), "alice").unwrap();
+    /// auth.save_token(Secret::from("token")).unwrap();
+    ///
+    /// // after restart
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"Id":"4f2a","Name":"alice"}"#));
+    /// let mut auth = AuthJellyfin::with_transport(Box::new(mock.clone())).unwrap();
+    /// assert!(auth.attach_store(Box::new(store.clone()), "alice").is_err());
+    ///
+    /// auth.set_device("Living room", "device-1");
+    /// assert!(auth.attach_store(Box::new(store), "alice").unwrap());
+    /// assert_eq!(auth.status(), AuthorizationStatus::TokenAcquired);
+    /// auth.verify().unwrap();
+    /// assert_eq!(auth.user_id(), Some("4f2a"));
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    ///
+    /// let request = &mock.requests()[0];
+    /// assert_eq!(request.url, "http://localhost:8096/Users/Me");
+    /// assert!(request.headers[0].1.contains("DeviceId=\"device-1\""));
+    /// ```
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.check_device()?;
+        self.user_id = None;
+        self.tokens.attach_store(store, account)
+    }
+
+    fn logout(&mut self) -> Result<(), Error> {
+        self.user_id = None;
+        self.tokens.logout()
+    }
+
+    /// End the session by `/Sessions/Logout` and forget the token.
+    /// API keys can be revoked only by the administrator, for them
+    /// the request fails and the key is only forgotten.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::jellyfin::AuthJellyfin;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+    /// use music_streamer::http::{Method, MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(204, ""));
+    ///
+    /// let mut auth = AuthJellyfin::with_transport(Box::new(mock.clone())).unwrap();
+    /// auth.save_token(Secret::from("token")).unwrap();
+    /// auth.revoke().unwrap();
+    ///
+    /// let request = &mock.requests()[0];
+    /// assert_eq!(request.method, Method::Post);
+    /// assert_eq!(request.url, "http://localhost:8096/Sessions/Logout");
+    /// assert!(request.headers[0].1.ends_with(", Token=\"token\""));
+    /// assert!(auth.get_token().is_err());
+    /// assert_eq!(auth.status(), AuthorizationStatus::Revoked);
+    /// ```
+    fn revoke(&mut self) -> Result<(), Error> {
+        if !self.tokens.has_token() {
+            return self.logout()
+        }
+
+        let request = Request::post(&self.endpoint("/Sessions/Logout"))
+            .header("Authorization", self.authorization_header().expose());
+        let answer = revoke_answer(self.transport.send(&request));
+
+        self.user_id = None;
+        self.tokens.revoke(answer)
+    }
+
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    /// Restore the session from the snapshot, the token is saved
+    /// to the attached store. Device id isn't part of the snapshot,
+    /// it has to be set by `set_device` first. The token stays
+    /// `TokenAcquired` until `verify` checks it and finds the user.
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        let has_token = snapshot.token.as_ref().is_some_and(|token| !token.is_empty());
+        if has_token {
+            self.check_device()?;
+        }
+        self.tokens.import(snapshot)?;
+        if has_token {
+            self.user_id = None;
+        }
+        Ok(())
+    }
+
+    fn as_credentials(&mut self) -> Option<&mut dyn CredentialAuthenticator> {
+        Some(self)
+    }
+}
+
+impl CredentialAuthenticator for AuthJellyfin {
+    /// Log the user in by `/Users/AuthenticateByName`.
+    /// Wrong credentials are returned as `Error::OAuth`.
+    fn login(&mut self, username: &str, password: &Secret) -> Result<(), Error> {
+        if username.is_empty() {
+            return Err(Error::InvalidState("user name is required"))
+        }
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+
+        let result = self.authenticate_by_name(username, password);
+        self.finish_login(result)
+    }
+
+    /// Log in by API key created in the dashboard, the key is checked
+    /// by `/System/Info`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::jellyfin::AuthJellyfin;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::{Error, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(401, ""));
+    /// mock.push_response(Response::new(200, r#"{"ServerName":"home"}"#));
+    ///
+    /// let mut auth = AuthJellyfin::with_transport(Box::new(mock.clone())).unwrap();
+    /// match auth.login_with_key(&Secret::from("wrong")) {
+    ///     Err(Error::OAuth(_)) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert_eq!(auth.status(), AuthorizationStatus::Failed);
+    /// assert!(auth.get_token().is_err());
+    ///
+    /// auth.login_with_key(&Secret::from("key")).unwrap();
+    /// assert_eq!(auth.get_token().unwrap().expose(), "key");
+    /// assert_eq!(auth.user_id(), None);
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert_eq!(mock.requests()[1].url, "http://localhost:8096/System/Info");
+    /// ```
+    fn login_with_key(&mut self, key: &Secret) -> Result<(), Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidState("API key is required"))
+        }
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+
+        let result = self.check(key).map(|()| (key.clone(), None));
+        self.finish_login(result)
+    }
+
+    /// Check the current token and find its user by `/Users/Me`,
+    /// API keys are checked by `/System/Info`. Refused token
+    /// moves the status to `Failed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::jellyfin::AuthJellyfin;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(400, ""));
+    /// mock.push_response(Response::new(200, r#"{"ServerName":"home"}"#));
+    ///
+    /// let mut auth = AuthJellyfin::with_transport(Box::new(mock.clone())).unwrap();
+    /// auth.save_token(Secret::from("key")).unwrap();
+    /// auth.verify().unwrap();
+    ///
+    /// assert_eq!(auth.user_id(), None);
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert_eq!(mock.requests()[1].url, "http://localhost:8096/System/Info");
+    /// ```
+    fn verify(&mut self) -> Result<(), Error> {
+        match self.current_user(self.get_token()?) {
+            Ok(user_id) => {
+                self.user_id = user_id;
+                self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+            }
+            Err(Error::OAuth(reason)) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -0,0 +1,535 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Last.fm implementation of authorization and authentication trait.
+//!
+//! Last.fm doesn't speak OAuth. The application gets a request token,
+//! the user approves it on the Last.fm page and the approved token is
+//! exchanged for a session key by `auth.getSession`. Desktop applications
+//! fetch the token by `auth.getToken` (`request_token`), web applications
+//! get it in the callback of the authorize link (`parse_response_code`).
+//! Session keys never expire, the user can only remove the application
+//! in the Last.fm settings.
+//!
+//! Every call of the API is signed by `api_sig`, see `AuthLastFm::sign`.
+//!
+//! # Examples
+//!
+//! Desktop flow:
+//!
+//! ```
+//! use music_streamer::auth::lastfm::AuthLastFm;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"token":"request-token"}"#));
+//! mock.push_response(Response::new(200, r#"{"session":{"name":"alice","key":"session-key",
+//!     "subscriber":0}}"#));
+//! let mut auth = AuthLastFm::with_transport(Box::new(mock.clone()));
+//! let secret = Secret::from("secret");
+//!
+//! let token = auth.request_token("key", &secret).unwrap();
+//! assert_eq!(auth.token_authorize_link("key", &token).unwrap(),
+//!            "https://www.last.fm/api/auth/?api_key=key&token=request-token");
+//! assert_eq!(auth.status(), AuthorizationStatus::UserAuthentication);
+//!
+//! // the user approved the token on the Last.fm page
+//! auth.authenticate_application("key", &secret, &token).unwrap();
+//! assert_eq!(auth.get_token().unwrap().expose(), "session-key");
+//! assert_eq!(auth.username(), Some("alice"));
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//! ```
+
+use super::Authenticator;
+use super::AuthorizationStatus;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::random::random_string;
+use super::redirect::{check_state, RedirectOutcome};
+use super::store::TokenStore;
+use super::token::TokenState;
+use super::uri::build_uri;
+use http::{HttpTransport, HyperTransport, Request};
+
+use std::time::{Duration, SystemTime};
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use rustc_serialize::json::Json;
+#[cfg(feature = "serde")]
+use super::snapshot::{deserialize_authenticator, serialize_authenticator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Session key gives full access to the account and never expires
+const GRANTED: &[Permission] = &[
+    Permission::BasicAccess,
+    Permission::OfflineAccess,
+    Permission::ManageLibrary,
+    Permission::ListeningHistory,
+];
+
+/// Number of random bytes in the state added to the callback
+const STATE_LENGTH: usize = 16;
+
+/// Store information about authorization progress and session key
+pub struct AuthLastFm {
+    tokens: TokenState,
+    username: Option<String>,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+    state: Option<Secret>,
+    pending: Option<Secret>,
+}
+
+impl AuthLastFm {
+    //! Authentication object for Last.fm.
+
+    /// Create new Last.fm authentication object
+    pub fn new() -> AuthLastFm {
+        AuthLastFm::with_transport(Box::new(HyperTransport::new()))
+    }
+
+    /// Create new Last.fm authentication object which sends
+    /// all requests by given transport
+    pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthLastFm {
+        AuthLastFm::with_config(AuthLastFm::default_config(), transport)
+    }
+
+    /// Create new Last.fm authentication object using given endpoints
+    /// and transport. Signed calls are sent to `config.token_url`.
+    pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>) -> AuthLastFm {
+        AuthLastFm {
+            tokens: TokenState::permanent(ServiceType::LastFm),
+            username: None,
+            transport,
+            config,
+            state: None,
+            pending: None,
+        }
+    }
+
+    /// Official Last.fm endpoints, Last.fm can't revoke sessions
+    pub fn default_config() -> ServiceConfig {
+        ServiceConfig {
+            auth_url: "https://www.last.fm/api/auth/".to_string(),
+            token_url: "https://ws.audioscrobbler.com/2.0/".to_string(),
+            api_url: "https://ws.audioscrobbler.com/2.0/".to_string(),
+            revoke_url: None,
+        }
+    }
+
+    /// Compute `api_sig` of the call: parameters sorted by name are
+    /// concatenated as `namevalue` pairs, followed by the application
+    /// secret, and hashed by MD5. `format` and `callback` are not signed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate crypto;
+    /// extern crate music_streamer;
+    /// # fn main() {
+    /// use crypto::digest::Digest;
+    /// use crypto::md5::Md5;
+    /// use music_streamer::auth::lastfm::AuthLastFm;
+    /// use music_streamer::auth::Secret;
+    ///
+    /// let sig = AuthLastFm::sign(&[("token", "t"), ("method", "auth.getSession"),
+    ///                              ("api_key", "k"), ("format", "json")],
+    ///                            &Secret::from("secret"));
+    ///
+    /// let mut md5 = Md5::new();
+    /// md5.input_str("api_keykmethodauth.getSessiontokentsecret");
+    /// assert_eq!(sig, md5.result_str());
+    /// # }
+    /// ```
+    pub fn sign(params: &[(&str, &str)], api_secret: &Secret) -> String {
+        let mut signed: Vec<_> = params.iter()
+            .filter(|&&(name, _)| name != "format" && name != "callback")
+            .collect();
+        signed.sort();
+
+        let mut md5 = Md5::new();
+        for &&(name, value) in &signed {
+            md5.input_str(name);
+            md5.input_str(value);
+        }
+        md5.input_str(api_secret.expose());
+        md5.result_str()
+    }
+
+    /// Get name of the user who approved the session, `None` when
+    /// the session key was restored from a store or snapshot
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(|name| &name[..])
+    }
+
+    /// Start desktop authorization by getting request token from
+    /// `auth.getToken`, the user then approves the token on the page
+    /// given by `token_authorize_link`. The status moves
+    /// to `UserAuthentication`. Only this token is accepted afterwards.
+    pub fn request_token(&mut self, api_key: &str, api_secret: &Secret) -> Result<Secret, Error> {
+        let json = self.call("auth.getToken", api_key, api_secret, &[])?;
+        let token = match json.find("token").and_then(|token| token.as_string()) {
+            Some(token) if !token.is_empty() => Secret::from(token),
+            _ => return Err(Error::Parse("no token in the auth.getToken response".to_string())),
+        };
+
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+        self.state = None;
+        self.pending = Some(token.clone());
+        Ok(token)
+    }
+
+    /// Check that the request token belongs to the authorization
+    /// in progress. Token of the desktop flow has to be the requested
+    /// one, callback of the web flow has to carry the state of the link.
+    fn check_token(&self, token: &Secret, state: Option<String>) -> Result<(), Error> {
+        match self.pending {
+            Some(ref pending) if pending == token => Ok(()),
+            Some(_) => Err(Error::StateMismatch),
+            None => check_state(self.state.as_ref(), state),
+        }
+    }
+
+    /// Create page where the user approves the request token in form:
+    ///
+    /// https://www.last.fm/api/auth/?api_key=YOUR_API_KEY&token=REQUEST_TOKEN
+    pub fn token_authorize_link(&self, api_key: &str, token: &Secret) -> Result<String, Error> {
+        build_uri(&self.config.auth_url, &[("api_key", api_key), ("token", token.expose())])
+    }
+
+    /// Send signed call of the auth method and return its JSON answer.
+    /// Errors of the API are returned as `Error::OAuth`.
+    fn call(&self, method: &str, api_key: &str, api_secret: &Secret,
+            params: &[(&str, &str)]) -> Result<Json, Error> {
+        let mut params = params.to_vec();
+        params.insert(0, ("method", method));
+        params.insert(0, ("api_key", api_key));
+        let sig = AuthLastFm::sign(&params, api_secret);
+        params.push(("api_sig", &sig));
+        params.push(("format", "json"));
+
+        let uri = build_uri(&self.config.token_url, &params)?;
+        let res = self.transport.send(&Request::get(&uri))?;
+        debug!("last.fm {} answered with status {}", method, res.status);
+
+        let answer = Secret::new(res.text());
+        let json = match Json::from_str(answer.expose()) {
+            Ok(json) => json,
+            Err(_) if !res.is_success() => return Err(Error::HttpStatus(res.status)),
+            Err(err) => return Err(Error::Parse(format!("invalid {} response: {}", method, err))),
+        };
+
+        if json.find("error").is_some() {
+            let reason = json.find("message").and_then(|reason| reason.as_string())
+                .unwrap_or("unknown error");
+            return Err(Error::OAuth(reason.to_string()))
+        }
+        if !res.is_success() {
+            return Err(Error::HttpStatus(res.status))
+        }
+
+        Ok(json)
+    }
+
+    /// Exchange approved request token for session key and user name
+    fn get_session(&self, api_key: &str, api_secret: &Secret, token: &Secret)
+                   -> Result<(Secret, Option<String>), Error> {
+        let json = self.call("auth.getSession", api_key, api_secret,
+                             &[("token", token.expose())])?;
+
+        let key = match json.find_path(&["session", "key"]).and_then(|key| key.as_string()) {
+            Some(key) if !key.is_empty() => Secret::from(key),
+            _ => return Err(Error::Parse("no session key in the auth.getSession response"
+                                         .to_string())),
+        };
+        let username = json.find_path(&["session", "name"])
+            .and_then(|name| name.as_string())
+            .map(str::to_string);
+
+        Ok((key, username))
+    }
+
+    /// Drop the authorization in progress and the user name
+    fn reset(&mut self) {
+        self.username = None;
+        self.state = None;
+        self.pending = None;
+    }
+}
+
+impl Default for AuthLastFm {
+    fn default() -> AuthLastFm {
+        AuthLastFm::new()
+    }
+}
+
+/// Authenticator is written as `export_snapshot(false)`, the token
+/// doesn't survive a serde round-trip. Serialize `export_snapshot(true)`
+/// to move the session with its token.
+#[cfg(feature = "serde")]
+impl Serialize for AuthLastFm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_authenticator(self, serializer)
+    }
+}
+
+/// Authenticator using official endpoints is restored from the snapshot
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AuthLastFm {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<AuthLastFm, D::Error> {
+        deserialize_authenticator(deserializer, |_| Some(AuthLastFm::new()))
+    }
+}
+
+impl Authenticator for AuthLastFm {
+
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get endpoints used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// Create uri for web authorization in form:
+    ///
+    /// https://www.last.fm/api/auth/?api_key=YOUR_API_KEY&cb=YOUR_CALLBACK?state=RANDOM_STATE
+    ///
+    /// Last.fm has no permissions, the session always gets full access
+    /// to the account. Callback receives the request token as `token`
+    /// next to the random state which is checked by `parse_response_code`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::lastfm::AuthLastFm;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission};
+    ///
+    /// let mut auth = AuthLastFm::new();
+    /// let link = auth.get_authorize_link("key", "http://example.com/cb?from=app",
+    ///                                    &[Permission::ListeningHistory]).unwrap();
+    /// assert!(link.starts_with("https://www.last.fm/api/auth/?api_key=key\
+    ///                           &cb=http%3A%2F%2Fexample.com%2Fcb%3Ffrom%3Dapp%26state%3D"));
+    /// assert_eq!(auth.status(), AuthorizationStatus::UserAuthentication);
+    ///
+    /// // Last.fm appends the token to the callback
+    /// let state = link.split("%26state%3D").nth(1).unwrap();
+    /// match auth.parse_response_code("http://example.com/cb?from=app&state=forged&token=abc") {
+    ///     Err(Error::StateMismatch) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// let callback = format!("http://example.com/cb?from=app&state={}&token=abc", state);
+    /// let token = auth.parse_response_code(&callback).unwrap();
+    /// assert_eq!(token.expose(), "abc");
+    /// assert_eq!(auth.status(), AuthorizationStatus::CodeReceived);
+    /// ```
+    fn get_authorize_link(&mut self, app_id: &str, redirect_uri: &str,
+                          _permissions: &[Permission]) -> Result<String, Error> {
+        let state = Secret::new(random_string(STATE_LENGTH)?);
+        let callback = build_uri(redirect_uri, &[("state", state.expose())])?;
+        let complete_uri = build_uri(&self.config.auth_url, &[
+            ("api_key", app_id),
+            ("cb", &callback),
+        ])?;
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+        self.state = Some(state);
+        self.pending = None;
+        Ok(complete_uri)
+    }
+
+    /// Get request token from the callback uri, the status moves
+    /// to `CodeReceived`. Token of another authorization is refused
+    /// with `Error::StateMismatch`.
+    fn parse_response_code(&mut self, response: &str) -> Result<Secret, Error> {
+        match RedirectOutcome::parse_with(response, "token") {
+            RedirectOutcome::Granted { code, state } => {
+                self.check_token(&code, state)?;
+                self.tokens.transition(AuthorizationStatus::CodeReceived)?;
+                Ok(code)
+            }
+            RedirectOutcome::Denied { reason } => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            RedirectOutcome::Malformed(msg) => Err(Error::Parse(msg)),
+        }
+    }
+
+    /// Exchange request token approved by the user for session key
+    /// by `auth.getSession`. `app_id` is the API key and `app_secret`
+    /// the shared secret of the application. Authorization has to be
+    /// started by `get_authorize_link` or `request_token` first,
+    /// failed exchange moves the status to `Failed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::lastfm::AuthLastFm;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(403, r#"{"error":14,"message":"Unauthorized Token"}"#));
+    /// mock.push_response(Response::new(200, r#"{"session":{"name":"alice","key":"sk"}}"#));
+    ///
+    /// let mut auth = AuthLastFm::with_transport(Box::new(mock.clone()));
+    /// auth.get_authorize_link("key", "http://example.com/cb", &[]).unwrap();
+    /// match auth.authenticate_application("key", &Secret::from("s"), &Secret::from("t")) {
+    ///     Err(Error::OAuth(reason)) => assert_eq!(reason, "Unauthorized Token"),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert_eq!(auth.status(), AuthorizationStatus::Failed);
+    ///
+    /// auth.get_authorize_link("key", "http://example.com/cb", &[]).unwrap();
+    /// auth.authenticate_application("key", &Secret::from("s"), &Secret::from("t")).unwrap();
+    /// assert_eq!(auth.get_token().unwrap().expose(), "sk");
+    /// assert_eq!(auth.expires_at().unwrap(), None);
+    /// assert!(auth.has_permission(Permission::ListeningHistory));
+    /// assert!(mock.requests()[1].url.starts_with("https://ws.audioscrobbler.com/2.0/\
+    ///                                             ?api_key=key&method=auth.getSession&token=t"));
+    /// ```
+    fn authenticate_application(&mut self, app_id: &str, app_secret: &Secret,
+                                code: &Secret) -> Result<(), Error> {
+        // token of the desktop flow doesn't go through parse_response_code
+        self.tokens.transition(AuthorizationStatus::CodeReceived)?;
+        if self.state.is_none() && self.pending.is_none() {
+            return Err(Error::InvalidState("no authorization was started"))
+        }
+        if self.pending.as_ref().is_some_and(|pending| pending != code) {
+            return Err(Error::StateMismatch)
+        }
+
+        let (key, username) = match self.get_session(app_id, app_secret, code) {
+            Ok(session) => session,
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                return Err(err)
+            }
+        };
+
+        self.save_token(key)?;
+        self.username = username;
+        self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+    }
+
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save session key, Last.fm session keys never expire
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::lastfm::AuthLastFm;
+    /// use music_streamer::auth::store::MemoryStore;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret, ServiceType};
+    /// use music_streamer::auth::store::TokenStore;
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut auth = AuthLastFm::new();
+    /// auth.attach_store(Box::new(store.clone()), "alice").unwrap();
+    /// auth.save_token(Secret::from("sk")).unwrap();
+    ///
+    /// let stored = store.load(ServiceType::LastFm, "alice").unwrap().unwrap();
+    /// assert_eq!(stored.token.expose(), "sk");
+    /// assert_eq!(stored.expires_at, None);
+    ///
+    /// let mut restored = AuthLastFm::new();
+    /// assert!(restored.attach_store(Box::new(store), "alice").unwrap());
+    /// assert_eq!(restored.get_token().unwrap().expose(), "sk");
+    /// assert_eq!(restored.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        // permissions are saved with the key, they go back when it isn't saved
+        let granted = self.tokens.granted().to_vec();
+        self.tokens.set_granted(GRANTED.to_vec());
+        if let Err(err) = self.tokens.save(token, expires_at) {
+            self.tokens.set_granted(granted);
+            return Err(err)
+        }
+        self.reset();
+        Ok(())
+    }
+
+    /// Get session key
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get().map_err(|_| Error::InvalidState("no session key was acquired yet"))
+    }
+
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// Last.fm session keys never expire
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Last.fm session keys never expire
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Session gives full access to the account
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.reset();
+        self.tokens.attach_store(store, account)
+    }
+
+    fn logout(&mut self) -> Result<(), Error> {
+        self.reset();
+        self.tokens.logout()
+    }
+
+    /// Last.fm can't end sessions by API, the session key is forgotten
+    /// and the user has to remove the application in the Last.fm settings
+    fn revoke(&mut self) -> Result<(), Error> {
+        self.reset();
+        self.tokens.revoke(Ok(()))
+    }
+
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        let has_token = snapshot.token.as_ref().is_some_and(|token| !token.is_empty());
+        self.tokens.import(snapshot)?;
+        if has_token {
+            self.reset();
+        }
+        Ok(())
+    }
+}
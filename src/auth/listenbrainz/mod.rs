@@ -0,0 +1,387 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! ListenBrainz implementation of authorization and authentication trait.
+//!
+//! ListenBrainz has no authorization links, the user copies the token
+//! from the profile page at https://listenbrainz.org/profile/ and the
+//! application checks it by `/1/validate-token`, which also tells the
+//! name of the user. Every API request carries `Authorization: Token ...`
+//! header. Self-hosted servers are used by changing `api_url`.
+//!
+//! # Examples
+//!
+//! ```
+//! use music_streamer::auth::listenbrainz::AuthListenBrainz;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+//! use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"code":200,"message":"Token valid.",
+//!     "valid":true,"user_name":"alice"}"#));
+//! let config = ServiceConfig::new(ServiceType::ListenBrainz)
+//!     .with_api_url("http://127.0.0.1:8100/");
+//! let mut auth = AuthListenBrainz::with_config(config, Box::new(mock.clone()));
+//! auth.login_with_key(&Secret::from("user-token")).unwrap();
+//!
+//! assert_eq!(auth.username(), Some("alice"));
+//! assert_eq!(auth.get_token().unwrap().expose(), "user-token");
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//!
+//! let request = &mock.requests()[0];
+//! assert_eq!(request.url, "http://127.0.0.1:8100/1/validate-token");
+//! assert!(request.headers.contains(&("Authorization".to_string(),
+//!                                    "Token user-token".to_string())));
+//! ```
+
+use super::Authenticator;
+use super::AuthorizationStatus;
+use super::CredentialAuthenticator;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::store::TokenStore;
+use super::token::TokenState;
+use http::{HttpTransport, HyperTransport, Request};
+
+use std::time::{Duration, SystemTime};
+use rustc_serialize::json::Json;
+
+/// Store information about authorization progress and token
+pub struct AuthListenBrainz {
+    tokens: TokenState,
+    username: Option<String>,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+}
+
+impl AuthListenBrainz {
+    //! Authentication object for ListenBrainz.
+
+    /// Create new ListenBrainz authentication object
+    pub fn new() -> AuthListenBrainz {
+        AuthListenBrainz::with_transport(Box::new(HyperTransport::new()))
+    }
+
+    /// Create new ListenBrainz authentication object which sends
+    /// all requests by given transport
+    pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthListenBrainz {
+        AuthListenBrainz::with_config(AuthListenBrainz::default_config(), transport)
+    }
+
+    /// Create new ListenBrainz authentication object for the server
+    /// at `config.api_url` using given transport
+    pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>)
+                       -> AuthListenBrainz {
+        AuthListenBrainz {
+            tokens: TokenState::permanent(ServiceType::ListenBrainz).with_verification(),
+            username: None,
+            transport,
+            config,
+        }
+    }
+
+    /// Official ListenBrainz server, only `api_url` is used
+    pub fn default_config() -> ServiceConfig {
+        ServiceConfig {
+            auth_url: String::new(),
+            token_url: String::new(),
+            api_url: "https://api.listenbrainz.org".to_string(),
+            revoke_url: None,
+        }
+    }
+
+    /// Get name of the user owning the token, known once the token
+    /// was checked by `login_with_key` or `verify`
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_ref().map(|name| &name[..])
+    }
+
+    /// Check the token by `/1/validate-token` and return the user name.
+    /// Refused token is returned as `Error::OAuth`.
+    fn validate(&self, token: &Secret) -> Result<String, Error> {
+        let url = format!("{}/1/validate-token", self.config.api_url.trim_end_matches('/'));
+        let header = Secret::new(format!("Token {}", token.expose()));
+        let request = Request::get(&url).header("Authorization", header.expose());
+
+        let res = self.transport.send(&request)?;
+        debug!("listenbrainz token validation answered with status {}", res.status);
+
+        let json = match Json::from_str(&res.text()) {
+            Ok(json) => json,
+            Err(_) if !res.is_success() => return Err(Error::HttpStatus(res.status)),
+            Err(err) => return Err(Error::Parse(format!("invalid validation response: {}", err))),
+        };
+        let reason = json.find("message").or_else(|| json.find("error"))
+            .and_then(|reason| reason.as_string())
+            .unwrap_or("token is not valid")
+            .to_string();
+
+        match json.find("valid").and_then(|valid| valid.as_boolean()) {
+            Some(true) => {
+                json.find("user_name").and_then(|name| name.as_string())
+                    .map(str::to_string)
+                    .ok_or_else(|| Error::Parse("no user name in the validation response"
+                                                .to_string()))
+            }
+            Some(false) => Err(Error::OAuth(reason)),
+            // missing or malformed tokens are answered by an error object
+            None if res.status == 400 || res.status == 401 => Err(Error::OAuth(reason)),
+            None if !res.is_success() => Err(Error::HttpStatus(res.status)),
+            None => Err(Error::Parse("invalid validation response".to_string())),
+        }
+    }
+}
+
+impl Default for AuthListenBrainz {
+    fn default() -> AuthListenBrainz {
+        AuthListenBrainz::new()
+    }
+}
+
+impl Authenticator for AuthListenBrainz {
+
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get server used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// ListenBrainz has no authorization page, use `login_with_key`
+    fn get_authorize_link(&mut self, _app_id: &str, _redirect_uri: &str,
+                          _permissions: &[Permission]) -> Result<String, Error> {
+        Err(Error::InvalidState("listenbrainz logs in by user token"))
+    }
+
+    /// ListenBrainz has no authorization page, use `login_with_key`
+    fn parse_response_code(&mut self, _response: &str) -> Result<Secret, Error> {
+        Err(Error::InvalidState("listenbrainz logs in by user token"))
+    }
+
+    /// ListenBrainz has no authorization codes, use `login_with_key`
+    fn authenticate_application(&mut self, _app_id: &str, _app_secret: &Secret,
+                                _code: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("listenbrainz logs in by user token"))
+    }
+
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save token without checking it, ListenBrainz tokens never expire
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        self.tokens.save(token, expires_at)?;
+        self.username = None;
+        Ok(())
+    }
+
+    /// Get user token
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get().map_err(|_| Error::InvalidState("user is not logged in"))
+    }
+
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// ListenBrainz tokens never expire
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// ListenBrainz tokens never expire
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// ListenBrainz has no permissions, the token gives full access
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    /// Attach store and restore token of the account from it.
+    /// Restored token stays `TokenAcquired` until `verify` checks it
+    /// and resolves the user name.
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.username = None;
+        self.tokens.attach_store(store, account)
+    }
+
+    fn logout(&mut self) -> Result<(), Error> {
+        self.username = None;
+        self.tokens.logout()
+    }
+
+    /// ListenBrainz can't reset tokens by API, the token is forgotten
+    /// and the user has to reset it on the profile page
+    fn revoke(&mut self) -> Result<(), Error> {
+        self.username = None;
+        self.tokens.revoke(Ok(()))
+    }
+
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    /// Restore the session from the snapshot, the token is saved
+    /// to the attached store. Like a token from the store it stays
+    /// `TokenAcquired` until `verify` checks it and resolves the user name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::listenbrainz::AuthListenBrainz;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mut auth = AuthListenBrainz::new();
+    /// auth.save_token(Secret::from("user-token")).unwrap();
+    /// let mut snapshot = auth.export_snapshot(true);
+    /// snapshot.status = AuthorizationStatus::AuthorizationCompleted;
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"valid":true,"user_name":"alice"}"#));
+    /// let mut restored = AuthListenBrainz::with_transport(Box::new(mock));
+    /// restored.import_snapshot(&snapshot).unwrap();
+    /// assert_eq!(restored.get_token().unwrap().expose(), "user-token");
+    /// assert_eq!(restored.status(), AuthorizationStatus::TokenAcquired);
+    ///
+    /// restored.verify().unwrap();
+    /// assert_eq!(restored.username(), Some("alice"));
+    /// assert_eq!(restored.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        let has_token = snapshot.token.as_ref().is_some_and(|token| !token.is_empty());
+        self.tokens.import(snapshot)?;
+        if has_token {
+            self.username = None;
+        }
+        Ok(())
+    }
+
+    fn as_credentials(&mut self) -> Option<&mut dyn CredentialAuthenticator> {
+        Some(self)
+    }
+}
+
+impl CredentialAuthenticator for AuthListenBrainz {
+    /// Log in by the user token, the token is checked by `/1/validate-token`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::listenbrainz::AuthListenBrainz;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::{Error, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// let invalid = r#"{"code":200,"message":"Token invalid.","valid":false}"#;
+    /// let refused = r#"{"code":401,"error":"Invalid authorization token."}"#;
+    /// mock.push_response(Response::new(200, invalid));
+    /// mock.push_response(Response::new(401, refused));
+    ///
+    /// let mut auth = AuthListenBrainz::with_transport(Box::new(mock.clone()));
+    /// match auth.login_with_key(&Secret::from("wrong")) {
+    ///     Err(Error::OAuth(reason)) => assert_eq!(reason, "Token invalid."),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert_eq!(auth.status(), AuthorizationStatus::Failed);
+    /// assert!(auth.get_token().is_err());
+    /// assert_eq!(auth.username(), None);
+    ///
+    /// match auth.login_with_key(&Secret::from("wrong")) {
+    ///     Err(Error::OAuth(reason)) => assert_eq!(reason, "Invalid authorization token."),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert_eq!(mock.requests()[0].url, "https://api.listenbrainz.org/1/validate-token");
+    /// ```
+    fn login_with_key(&mut self, key: &Secret) -> Result<(), Error> {
+        if key.is_empty() {
+            return Err(Error::InvalidState("user token is required"))
+        }
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+
+        match self.validate(key) {
+            Ok(username) => {
+                self.save_token(key.clone())?;
+                self.username = Some(username);
+                self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+            }
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Check the current token and resolve the user name, accepted token
+    /// completes the authorization and refused one moves the status to `Failed`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::listenbrainz::AuthListenBrainz;
+    /// use music_streamer::auth::store::MemoryStore;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut auth = AuthListenBrainz::new();
+    /// auth.attach_store(Box::new(store.clone()), "alice").unwrap();
+    /// auth.save_token(Secret::from("user-token")).unwrap();
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"valid":true,"user_name":"alice"}"#));
+    /// let mut restored = AuthListenBrainz::with_transport(Box::new(mock));
+    /// assert!(restored.attach_store(Box::new(store), "alice").unwrap());
+    /// assert_eq!(restored.status(), AuthorizationStatus::TokenAcquired);
+    /// assert_eq!(restored.username(), None);
+    ///
+    /// restored.verify().unwrap();
+    /// assert_eq!(restored.username(), Some("alice"));
+    /// assert_eq!(restored.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    fn verify(&mut self) -> Result<(), Error> {
+        match self.validate(self.get_token()?) {
+            Ok(username) => {
+                self.username = Some(username);
+                self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+            }
+            Err(Error::OAuth(reason)) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -14,28 +14,148 @@
 // along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
 
 //! General authorization and authentication trait
-//! as first Deezer will be using this trait more will come.
+//! implemented for Deezer, Spotify, SoundCloud, Last.fm, ListenBrainz,
+//! Subsonic and Jellyfin servers, more will come. Services where the user gives credentials
+//! to the application implement `CredentialAuthenticator` too.
 
-mod deezer;
+pub mod deezer;
+pub mod jellyfin;
+pub mod lastfm;
+pub mod listenbrainz;
+pub mod oauth2;
+pub mod redirect;
+pub mod soundcloud;
+pub mod spotify;
+pub mod store;
+pub mod subsonic;
+mod config;
+mod error;
+mod pkce;
+mod random;
+mod secret;
+mod session;
+mod snapshot;
+mod state;
+mod token;
+mod uri;
+
+pub use self::config::ServiceConfig;
+pub use self::error::Error;
+pub use self::secret::Secret;
+pub(crate) use self::secret::{wipe_bytes, wipe_json, wipe_string};
+pub use self::session::{SessionInfo, SessionManager};
+pub use self::snapshot::SessionSnapshot;
+pub use self::state::StatusObserver;
+
+use self::store::TokenStore;
+use http::{HttpTransport, HyperTransport};
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 /// Type of the service you want to create
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum ServiceType {
     DEEZER,
+    Spotify,
+    SoundCloud,
+    Subsonic,
+    Jellyfin,
+    LastFm,
+    ListenBrainz,
+}
+
+impl ServiceType {
+    /// Name of the service used as a key in token stores
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ServiceType::DEEZER => "deezer",
+            ServiceType::Spotify => "spotify",
+            ServiceType::SoundCloud => "soundcloud",
+            ServiceType::Subsonic => "subsonic",
+            ServiceType::Jellyfin => "jellyfin",
+            ServiceType::LastFm => "lastfm",
+            ServiceType::ListenBrainz => "listenbrainz",
+        }
+    }
+}
+
+impl FromStr for ServiceType {
+    type Err = Error;
+
+    /// Parse service from its name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::ServiceType;
+    ///
+    /// assert_eq!("deezer".parse::<ServiceType>().unwrap(), ServiceType::DEEZER);
+    /// assert!("unknown".parse::<ServiceType>().is_err());
+    /// ```
+    fn from_str(name: &str) -> Result<ServiceType, Error> {
+        match name {
+            "deezer" => Ok(ServiceType::DEEZER),
+            "spotify" => Ok(ServiceType::Spotify),
+            "soundcloud" => Ok(ServiceType::SoundCloud),
+            "subsonic" => Ok(ServiceType::Subsonic),
+            "jellyfin" => Ok(ServiceType::Jellyfin),
+            "lastfm" => Ok(ServiceType::LastFm),
+            "listenbrainz" => Ok(ServiceType::ListenBrainz),
+            _ => Err(Error::Parse(format!("unknown service '{}'", name))),
+        }
+    }
 }
 
-/// Progress status of the authorization
+/// Progress status of the authorization.
+///
+/// Status can change only this way, anything else is rejected
+/// with `Error::InvalidTransition`:
+///
+/// ```text
+///          +------------------------------------+
+///          |                                    v
+/// Nothing -+-> UserAuthentication -+----> TokenAcquired ----> AuthorizationCompleted
+///                      |           |        ^    |    |                  |    |
+///                      v           |        |    |    +----> Expired <---+    |
+///                CodeReceived -----+        |    |            |  |            |
+///                                           +----|------------+  |            |
+///                                                |               v            |
+///                                                +----------> Revoked <-------+
+/// ```
+///
+/// Token can be acquired directly (saved by hand, implicit flow),
+/// replaced by another one or refreshed once it expired. Restoring
+/// from a store or snapshot resets the status to `Nothing` first
+/// and then goes through `TokenAcquired`. Authorization can be
+/// started again, reset to `Nothing` or fail at any point.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum AuthorizationStatus {
     /// Authorization doesn't started yet
     Nothing,
     /// User authenticate self on the website
     UserAuthentication,
+    /// Browser came back with the code
+    CodeReceived,
     /// Get token from user
-    TokenAquired,
+    TokenAcquired,
     /// Authorization is completed - can start using service
     AuthorizationCompleted,
+    /// Token run out - user has to authorize the application again
+    Expired,
+    /// Token was revoked - user logged out
+    Revoked,
+    /// Authorization failed or was refused by the user
+    Failed,
 }
 
 /// Possible permissions which application can have
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Permission {
     /// Access users basic information
     BasicAccess,
@@ -53,38 +173,291 @@ pub enum Permission {
     ListeningHistory,
 }
 
+impl Permission {
+    /// All permissions in the order they are declared
+    pub fn all() -> &'static [Permission] {
+        const ALL: &[Permission] = &[
+            Permission::BasicAccess,
+            Permission::Email,
+            Permission::OfflineAccess,
+            Permission::ManageLibrary,
+            Permission::ManageCommunity,
+            Permission::DeleteLibrary,
+            Permission::ListeningHistory,
+        ];
+        ALL
+    }
+
+    /// Name of the permission as used by Deezer
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Permission::BasicAccess => "basic_access",
+            Permission::Email => "email",
+            Permission::OfflineAccess => "offline_access",
+            Permission::ManageLibrary => "manage_library",
+            Permission::ManageCommunity => "manage_community",
+            Permission::DeleteLibrary => "delete_library",
+            Permission::ListeningHistory => "listening_history",
+        }
+    }
+
+    /// Join names of the permissions with commas, duplicates are skipped
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::Permission;
+    ///
+    /// let perms = [Permission::BasicAccess, Permission::Email, Permission::BasicAccess];
+    /// assert_eq!(Permission::join(&perms), "basic_access,email");
+    /// ```
+    pub fn join(permissions: &[Permission]) -> String {
+        Permission::dedup(permissions).iter()
+            .map(|perm| perm.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Remove duplicates and keep the order of the first occurrence
+    pub fn dedup(permissions: &[Permission]) -> Vec<Permission> {
+        let mut unique = Vec::with_capacity(permissions.len());
+        for perm in permissions {
+            if !unique.contains(perm) {
+                unique.push(*perm);
+            }
+        }
+        unique
+    }
+}
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    /// Parse permission from its name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::Permission;
+    ///
+    /// for perm in Permission::all() {
+    ///     assert_eq!(perm.as_str().parse::<Permission>().unwrap(), *perm);
+    /// }
+    /// assert!("unknown".parse::<Permission>().is_err());
+    /// ```
+    fn from_str(name: &str) -> Result<Permission, Error> {
+        Permission::all().iter()
+            .find(|perm| perm.as_str() == name)
+            .cloned()
+            .ok_or_else(|| Error::Parse(format!("unknown permission '{}'", name)))
+    }
+}
+
 /// Create instance of Authenticator which provides access to
 /// ServiceType service.
-pub fn new(service: ServiceType) -> Box<Authenticator> {
+pub fn new(service: ServiceType) -> Result<Box<dyn Authenticator>, Error> {
+    with_config(service, ServiceConfig::new(service), Box::new(HyperTransport::new()))
+}
+
+/// Create instance of Authenticator using given endpoints and transport
+/// instead of the official ones.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth;
+/// use music_streamer::auth::{Permission, Secret, ServiceConfig, ServiceType};
+/// use music_streamer::http::{MockTransport, Response};
+///
+/// let mock = MockTransport::new();
+/// mock.push_response(Response::new(200, "access_token=token&expires=3600"));
+///
+/// let config = ServiceConfig::new(ServiceType::DEEZER)
+///     .with_token_url("http://127.0.0.1:8080/access_token.php");
+/// let mut auth = auth::with_config(ServiceType::DEEZER, config, Box::new(mock.clone())).unwrap();
+/// auth.get_authorize_link("111", "http://example.com", &[Permission::BasicAccess]).unwrap();
+/// auth.authenticate_application("111", &Secret::from("secret"), &Secret::from("code"))
+///     .unwrap();
+///
+/// assert!(mock.requests()[0].url.starts_with("http://127.0.0.1:8080/access_token.php?"));
+/// ```
+pub fn with_config(service: ServiceType, config: ServiceConfig, transport: Box<dyn HttpTransport>)
+                   -> Result<Box<dyn Authenticator>, Error> {
     match service {
         ServiceType::DEEZER => {
-            Box::new(deezer::AuthDeezer::new())
+            Ok(Box::new(deezer::AuthDeezer::with_config(config, transport)))
+        }
+        ServiceType::Spotify => {
+            Ok(Box::new(spotify::with_config(config, transport)))
+        }
+        ServiceType::SoundCloud => {
+            Ok(Box::new(soundcloud::with_config(config, transport)))
+        }
+        ServiceType::Subsonic => {
+            Ok(Box::new(subsonic::AuthSubsonic::with_config(config, transport)))
         }
+        ServiceType::Jellyfin => {
+            Ok(Box::new(jellyfin::AuthJellyfin::with_config(config, transport)?))
+        }
+        ServiceType::LastFm => {
+            Ok(Box::new(lastfm::AuthLastFm::with_config(config, transport)))
+        }
+        ServiceType::ListenBrainz => {
+            Ok(Box::new(listenbrainz::AuthListenBrainz::with_config(config, transport)))
+        }
+    }
+}
+
+/// Provider of the service exposed by its module, `None` if the service
+/// doesn't use the generic OAuth 2.0 authenticator
+#[cfg(feature = "serde")]
+pub(crate) fn oauth2_provider(service: ServiceType) -> Option<oauth2::Provider> {
+    match service {
+        ServiceType::Spotify => Some(spotify::provider()),
+        ServiceType::SoundCloud => Some(soundcloud::provider()),
+        _ => None,
     }
 }
 
 pub trait Authenticator {
     /// Get status of ongoing authentication
-    fn status(&self) -> &AuthorizationStatus;
+    fn status(&self) -> AuthorizationStatus;
+
+    /// Register callback which receives every status change
+    fn on_status_change(&mut self, observer: StatusObserver);
 
-    /// Return uri for user to authorize the application in his account
+    /// Get endpoints used by the authenticator
+    fn config(&self) -> &ServiceConfig;
+
+    /// Return uri for user to authorize the application in his account.
+    /// Random `state` is part of the uri and it is checked when
+    /// the response is parsed.
     fn get_authorize_link(&mut self, app_id: &str, redirect_uri: &str, permissions: &[Permission])
-                          -> String;
+                          -> Result<String, Error>;
 
     /// Get code from response returned by browser after app
-    /// authorization is completed by user
-    fn parse_response_code(&self, response: &str) -> Option<String>;
+    /// authorization is completed by user.
+    /// Fails with `Error::StateMismatch` when the response doesn't belong
+    /// to the authorization started by `get_authorize_link`.
+    fn parse_response_code(&mut self, response: &str) -> Result<Secret, Error>;
 
     /// Authenticate application with generated code from authorization process
-    fn authenticate_application(&mut self, app_id: &str, app_secret: &str, code: &str) -> Result<(), &str>;
+    fn authenticate_application(&mut self, app_id: &str, app_secret: &Secret, code: &Secret)
+                                -> Result<(), Error>;
 
     /// Save token to authentication object
     /// Incomming token will be moved so it won't be usable anymore
     /// for security reasons
-    fn save_token(&mut self, token: String);
+    fn save_token(&mut self, token: Secret) -> Result<(), Error>;
+
+    /// Save token together with the time when it stops being valid.
+    /// `None` means the token never expires.
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error>;
 
     /// Get active user token
     /// 
     /// DO NOT STORE THE TOKEN ELSEWHERE
-    fn get_token(&self) -> String;
+    fn get_token(&self) -> Result<&Secret, Error>;
+
+    /// Check if there is a token which didn't expire yet
+    fn is_token_valid(&self) -> bool;
+
+    /// Get time when the token expires, `None` if it never expires
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error>;
+
+    /// Get how long the token will be valid, `None` if it never expires.
+    /// Expired token has zero time remaining.
+    fn time_remaining(&self) -> Result<Option<Duration>, Error>;
+
+    /// Get permissions granted by the user to the application.
+    /// Empty until the authorization is completed.
+    fn granted_permissions(&self) -> &[Permission];
+
+    /// Check if the permission was granted
+    fn has_permission(&self, permission: Permission) -> bool {
+        self.granted_permissions().contains(&permission)
+    }
+
+    /// Attach store which keeps the token of given account between restarts.
+    /// Token saved in the store is restored and every newly acquired
+    /// token is saved to the store.
+    /// Returns true when a valid token was restored, the authorization
+    /// is completed in that case. Tokens of `CredentialAuthenticator`
+    /// stay `TokenAcquired` until `verify` completes the authorization.
+    /// Expired token which can be refreshed is restored too, false
+    /// is returned and the status is `Expired`.
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error>;
+
+    /// Forget the token and granted permissions, the token is deleted
+    /// from the attached store too. Status goes back to `Nothing`.
+    fn logout(&mut self) -> Result<(), Error>;
+
+    /// Revoke the token at the service when it has revoke endpoint
+    /// and log out. Status is `Revoked` when a token was revoked.
+    fn revoke(&mut self) -> Result<(), Error>;
+
+    /// Get new token with the refresh token issued together with
+    /// the current one. Fails with `Error::InvalidState` when
+    /// the service doesn't issue refresh tokens.
+    fn refresh(&mut self, _app_id: &str, _app_secret: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("service doesn't issue refresh tokens"))
+    }
+
+    /// Export the session so it can be moved to another process.
+    /// Token is part of the snapshot only when `include_secrets` is true.
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot;
+
+    /// Restore the session exported by `export_snapshot`.
+    /// Snapshot without token doesn't change the session.
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error>;
+
+    /// Get the authenticator as `CredentialAuthenticator` when the user
+    /// logs in by credentials instead of the authorize link
+    fn as_credentials(&mut self) -> Option<&mut dyn CredentialAuthenticator> {
+        None
+    }
+}
+
+/// Authenticator of services where the user gives credentials to
+/// the application instead of authorizing it in the browser.
+/// Methods of `Authenticator` working with authorize links and codes
+/// fail with `Error::InvalidState` for these services.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth;
+/// use music_streamer::auth::{Secret, ServiceType};
+/// use music_streamer::http::{MockTransport, Response};
+///
+/// let mock = MockTransport::new();
+/// mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"ok"}}"#));
+///
+/// let config = auth::ServiceConfig::new(ServiceType::Subsonic);
+/// let mut auth = auth::with_config(ServiceType::Subsonic, config, Box::new(mock)).unwrap();
+/// match auth.as_credentials() {
+///     Some(credentials) => credentials.login("alice", &Secret::from("sesame")).unwrap(),
+///     None => panic!("subsonic logs in by credentials"),
+/// }
+/// assert!(auth.get_token().is_ok());
+/// ```
+pub trait CredentialAuthenticator: Authenticator {
+    /// Log the user in by name and password,
+    /// the status moves to `AuthorizationCompleted`.
+    /// Fails with `Error::InvalidState` when the service has no passwords.
+    fn login(&mut self, _username: &str, _password: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("service doesn't log in by password"))
+    }
+
+    /// Log in by a key issued by the service in advance.
+    /// Fails with `Error::InvalidState` when the service doesn't issue keys.
+    fn login_with_key(&mut self, _key: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("service doesn't issue keys"))
+    }
+
+    /// Check that the service still accepts the current token, used
+    /// after the token is restored from a store or a snapshot.
+    /// Accepted token moves the status to `AuthorizationCompleted`.
+    fn verify(&mut self) -> Result<(), Error>;
 }
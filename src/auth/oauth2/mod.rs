@@ -0,0 +1,671 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Authenticator of services following OAuth 2.0 (RFC 6749).
+//! GenericOAuth2 is configured by a `Provider` describing how the service
+//! differs from the standard, services are then only thin configurations.
+
+use super::Authenticator;
+use super::AuthorizationStatus;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::pkce::{self, Pkce};
+use super::random::random_string;
+use super::redirect::{check_state, RedirectOutcome};
+use super::store::TokenStore;
+use super::token::{revoke_answer, TokenState};
+use super::uri::{build_form, build_uri};
+use http::{HttpTransport, HyperTransport, Request, Response};
+
+use std::time::{Duration, SystemTime};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+#[cfg(feature = "serde")]
+use super::oauth2_provider;
+#[cfg(feature = "serde")]
+use super::snapshot::{deserialize_authenticator, serialize_authenticator};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Number of random bytes in the state parameter
+const STATE_LENGTH: usize = 16;
+
+/// Way how the application proves its identity to the token endpoint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// `client_id` and `client_secret` are sent in the form body
+    Body,
+    /// `client_id:client_secret` is sent in `Authorization: Basic` header
+    Basic,
+}
+
+/// Description of an OAuth 2.0 service
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::oauth2::{ClientAuth, GenericOAuth2, Provider};
+/// use music_streamer::auth::{Authenticator, Permission, ServiceConfig, ServiceType};
+/// use music_streamer::http::MockTransport;
+///
+/// fn scopes(permission: Permission) -> Option<&'static [&'static str]> {
+///     match permission {
+///         Permission::BasicAccess => Some(&["profile"]),
+///         Permission::OfflineAccess => Some(&[]),
+///         _ => None,
+///     }
+/// }
+///
+/// let provider = Provider::new(ServiceType::Spotify, scopes)
+///     .with_client_auth(ClientAuth::Basic)
+///     .with_pkce(false);
+/// let config = ServiceConfig::new(ServiceType::Spotify)
+///     .with_auth_url("https://example.com/authorize");
+///
+/// let mut auth = GenericOAuth2::with_config(provider, config, Box::new(MockTransport::new()));
+/// let link = auth.get_authorize_link("111", "http://localhost/cb",
+///                                    &[Permission::BasicAccess]).unwrap();
+/// assert!(link.starts_with("https://example.com/authorize?client_id=111&response_type=code\
+///                           &redirect_uri=http%3A%2F%2Flocalhost%2Fcb&scope=profile&state="));
+/// ```
+#[derive(Clone, Copy)]
+pub struct Provider {
+    /// Service the tokens belong to, used as a key in token stores
+    pub service: ServiceType,
+    /// Scopes needed for the permission, `None` when the service
+    /// can't grant the permission at all
+    pub scopes: fn(Permission) -> Option<&'static [&'static str]>,
+    /// Separator of scopes in the `scope` parameter
+    pub scope_separator: &'static str,
+    /// Way how the application secret is sent
+    pub client_auth: ClientAuth,
+    /// Send PKCE challenge in the authorize link and verifier with the code
+    pub pkce: bool,
+}
+
+impl Provider {
+    //! OAuth 2.0 service description builder.
+
+    /// Create provider with space separated scopes, secret sent
+    /// in the body and PKCE turned on
+    pub fn new(service: ServiceType, scopes: fn(Permission) -> Option<&'static [&'static str]>)
+               -> Provider {
+        Provider {
+            service,
+            scopes,
+            scope_separator: " ",
+            client_auth: ClientAuth::Body,
+            pkce: true,
+        }
+    }
+
+    /// Change separator of the scopes
+    pub fn with_scope_separator(mut self, separator: &'static str) -> Provider {
+        self.scope_separator = separator;
+        self
+    }
+
+    /// Change the way how the application secret is sent
+    pub fn with_client_auth(mut self, client_auth: ClientAuth) -> Provider {
+        self.client_auth = client_auth;
+        self
+    }
+
+    /// Turn PKCE on or off
+    pub fn with_pkce(mut self, pkce: bool) -> Provider {
+        self.pkce = pkce;
+        self
+    }
+
+    /// Join scopes of the permissions, every scope is sent once.
+    /// Permissions the service can't grant add no scope.
+    pub fn scope(&self, permissions: &[Permission]) -> String {
+        let mut scopes: Vec<&str> = Vec::new();
+        for perm in permissions {
+            for scope in (self.scopes)(*perm).unwrap_or(&[]) {
+                if !scopes.contains(scope) {
+                    scopes.push(scope);
+                }
+            }
+        }
+        scopes.join(self.scope_separator)
+    }
+}
+
+/// Token endpoint answer
+struct TokenResponse {
+    token: Secret,
+    expires_at: Option<SystemTime>,
+    refresh_token: Option<Secret>,
+    /// Scopes granted by the user, `None` if the service didn't send them
+    scope: Option<String>,
+}
+
+/// Store information about authorization progress and tokens
+pub struct GenericOAuth2 {
+    provider: Provider,
+    tokens: TokenState,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+    requested: Vec<Permission>,
+    state: Option<Secret>,
+    pkce: Option<Pkce>,
+    redirect_uri: String,
+}
+
+impl GenericOAuth2 {
+    //! Authentication object for OAuth 2.0 services.
+
+    /// Create authentication object using official endpoints of the service
+    pub fn new(provider: Provider) -> GenericOAuth2 {
+        GenericOAuth2::with_transport(provider, Box::new(HyperTransport::new()))
+    }
+
+    /// Create authentication object which sends all requests by given transport
+    pub fn with_transport(provider: Provider, transport: Box<dyn HttpTransport>)
+                          -> GenericOAuth2 {
+        let config = ServiceConfig::new(provider.service);
+        GenericOAuth2::with_config(provider, config, transport)
+    }
+
+    /// Create authentication object using given endpoints and transport
+    pub fn with_config(provider: Provider, config: ServiceConfig,
+                       transport: Box<dyn HttpTransport>) -> GenericOAuth2 {
+        GenericOAuth2 {
+            provider,
+            tokens: TokenState::new(provider.service),
+            transport,
+            config,
+            requested: Vec::new(),
+            state: None,
+            pkce: None,
+            redirect_uri: String::new(),
+        }
+    }
+
+    /// Get description of the service
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
+    /// Get token of the application itself (client credentials grant).
+    /// The token gives access only to public data, no permission is granted.
+    /// Received token replaces the authorization in progress, the status
+    /// starts over from `Nothing`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::oauth2::{ClientAuth, GenericOAuth2, Provider};
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission, Secret};
+    /// use music_streamer::auth::{ServiceConfig, ServiceType};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// fn scopes(_: Permission) -> Option<&'static [&'static str]> {
+    ///     Some(&[])
+    /// }
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"access_token":"app","expires_in":3600}"#));
+    /// let provider = Provider::new(ServiceType::SoundCloud, scopes)
+    ///     .with_client_auth(ClientAuth::Basic);
+    /// let config = ServiceConfig::new(ServiceType::SoundCloud)
+    ///     .with_token_url("http://127.0.0.1:8080/oauth/token");
+    ///
+    /// let mut auth = GenericOAuth2::with_config(provider, config, Box::new(mock.clone()));
+    /// auth.authenticate_client("111", &Secret::from("secret")).unwrap();
+    ///
+    /// assert_eq!(auth.get_token().unwrap().expose(), "app");
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert!(auth.granted_permissions().is_empty());
+    /// assert_eq!(String::from_utf8(mock.requests()[0].body.clone()).unwrap(),
+    ///            "grant_type=client_credentials");
+    /// ```
+    pub fn authenticate_client(&mut self, app_id: &str, app_secret: &Secret)
+                               -> Result<(), Error> {
+        if app_secret.is_empty() {
+            return Err(Error::InvalidState("client credentials need the application secret"))
+        }
+
+        let response = self.request_token(&[("grant_type", "client_credentials")],
+                                          app_id, app_secret);
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                return Err(err)
+            }
+        };
+
+        self.tokens.transition(AuthorizationStatus::Nothing)?;
+        // refresh token of the user must not turn it back into user session
+        self.tokens.set_refresh_token(None);
+        self.tokens.set_granted(Vec::new());
+        self.requested.clear();
+        self.state = None;
+        self.pkce = None;
+        self.accept_token(response)
+    }
+
+    /// Get refresh token, `None` if the service didn't issue it
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    pub fn refresh_token(&self) -> Option<&Secret> {
+        self.tokens.refresh_token()
+    }
+
+    /// Requested permissions which all scopes were granted.
+    /// Permissions the service can't grant are never granted.
+    fn granted_by_scope(&self, scope: &Option<String>, refresh: bool) -> Vec<Permission> {
+        // without scope in the answer everything requested was granted
+        let granted: Option<Vec<&str>> = scope.as_ref().map(|scope| {
+            scope.split([' ', ','])
+                .filter(|scope| !scope.is_empty())
+                .collect()
+        });
+
+        self.requested.iter().cloned().filter(|perm| {
+            let needed = match (self.provider.scopes)(*perm) {
+                Some(needed) => needed,
+                None => return false,
+            };
+            match (*perm, granted.as_ref()) {
+                // offline access is proven by the refresh token
+                (Permission::OfflineAccess, Some(_)) if needed.is_empty() => refresh,
+                (_, Some(granted)) => needed.iter().all(|scope| granted.contains(scope)),
+                (_, None) => true,
+            }
+        }).collect()
+    }
+
+    /// Send form to the token endpoint and read its answer.
+    /// Application without secret is identified only by `client_id`.
+    fn request_token(&self, params: &[(&str, &str)], app_id: &str, app_secret: &Secret)
+                     -> Result<TokenResponse, Error> {
+        let mut form = params.to_vec();
+        let mut request = Request::post(&self.config.token_url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept", "application/json");
+
+        match self.provider.client_auth {
+            _ if app_secret.is_empty() => form.push(("client_id", app_id)),
+            ClientAuth::Body => {
+                form.push(("client_id", app_id));
+                form.push(("client_secret", app_secret.expose()));
+            }
+            ClientAuth::Basic => {
+                let credentials = Secret::new(format!("{}:{}", app_id, app_secret.expose()));
+                let header = Secret::new(format!("Basic {}",
+                                                 credentials.expose().as_bytes().to_base64(STANDARD)));
+                request = request.header("Authorization", header.expose());
+            }
+        }
+
+        let res = self.transport.send(&request.body(build_form(&form)))?;
+        debug!("{} token endpoint answered with status {}", self.provider.service.name(),
+               res.status);
+        GenericOAuth2::extract_token(&res)
+    }
+
+    /// Parse JSON answer of the token endpoint, errors are sent as
+    /// `{"error": "invalid_grant", "error_description": "..."}`
+    fn extract_token(res: &Response) -> Result<TokenResponse, Error> {
+        let body = Secret::new(res.text());
+        let json = match Json::from_str(body.expose()) {
+            Ok(json) => json,
+            Err(_) if !res.is_success() => return Err(Error::HttpStatus(res.status)),
+            Err(err) => return Err(Error::Parse(format!("invalid token response: {}", err))),
+        };
+
+        if let Some(error) = json.find("error") {
+            let reason = json.find("error_description").and_then(|desc| desc.as_string())
+                .or_else(|| error.as_string())
+                .unwrap_or("unknown error");
+            return Err(Error::OAuth(reason.to_string()))
+        }
+        if !res.is_success() {
+            return Err(Error::HttpStatus(res.status))
+        }
+
+        let token = match json.find("access_token").and_then(|token| token.as_string()) {
+            Some(token) if !token.is_empty() => Secret::from(token),
+            _ => return Err(Error::Parse("no token in the token response".to_string())),
+        };
+        let expires_at = match json.find("expires_in") {
+            None | Some(&Json::Null) => None,
+            Some(expires) => {
+                let seconds = expires.as_u64()
+                    .ok_or_else(|| Error::Parse("invalid expires_in value".to_string()))?;
+                Some(SystemTime::now() + Duration::from_secs(seconds))
+            }
+        };
+        let refresh_token = json.find("refresh_token").and_then(|token| token.as_string())
+            .filter(|token| !token.is_empty())
+            .map(Secret::from);
+        let scope = json.find("scope").and_then(|scope| scope.as_string()).map(str::to_string);
+
+        Ok(TokenResponse { token, expires_at, refresh_token, scope })
+    }
+
+    /// Remember tokens from the token endpoint and complete the authorization
+    fn accept_token(&mut self, response: TokenResponse) -> Result<(), Error> {
+        if response.refresh_token.is_some() {
+            self.tokens.set_refresh_token(response.refresh_token);
+        }
+        let granted = self.granted_by_scope(&response.scope,
+                                            self.tokens.refresh_token().is_some());
+        self.tokens.set_granted(granted);
+        if self.tokens.status() == AuthorizationStatus::AuthorizationCompleted {
+            // refreshed before it expired, the authorization goes on
+            return self.tokens.renew(response.token, response.expires_at)
+        }
+        self.save_token_with_expiry(response.token, response.expires_at)?;
+        self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+    }
+}
+
+/// Authenticator is written as `export_snapshot(false)`, the token
+/// doesn't survive a serde round-trip. Serialize `export_snapshot(true)`
+/// to move the session with its token.
+#[cfg(feature = "serde")]
+impl Serialize for GenericOAuth2 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_authenticator(self, serializer)
+    }
+}
+
+/// Authenticator of the snapshot service using official endpoints
+/// is restored from the snapshot
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for GenericOAuth2 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<GenericOAuth2, D::Error> {
+        deserialize_authenticator(deserializer, |service| {
+            oauth2_provider(service).map(GenericOAuth2::new)
+        })
+    }
+}
+
+impl Authenticator for GenericOAuth2 {
+
+    /// Get status of ongoing authentication.
+    /// Once the token runs out the status is `Expired` until it is refreshed.
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get endpoints used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// Create uri for user authentication in form:
+    ///
+    /// AUTH_URL?client_id=CLIENT_ID&response_type=code&redirect_uri=REDIRECT_URI&scope=SCOPES&code_challenge_method=S256&code_challenge=CHALLENGE&state=RANDOM_STATE
+    ///
+    /// Scope is left out when no permission needs one, PKCE challenge
+    /// is sent only when the provider uses PKCE. New random state
+    /// and PKCE verifier are generated for every link.
+    fn get_authorize_link(&mut self, app_id: &str, redirect_uri: &str, permissions: &[Permission])
+                          -> Result<String, Error> {
+        let state = Secret::new(random_string(STATE_LENGTH)?);
+        let pkce = if self.provider.pkce { Some(Pkce::new()?) } else { None };
+        let scope = self.provider.scope(permissions);
+
+        let mut params = vec![
+            ("client_id", app_id),
+            ("response_type", "code"),
+            ("redirect_uri", redirect_uri),
+        ];
+        if !scope.is_empty() {
+            params.push(("scope", &scope));
+        }
+        if let Some(ref pkce) = pkce {
+            params.push(("code_challenge_method", pkce::METHOD));
+            params.push(("code_challenge", &pkce.challenge));
+        }
+        params.push(("state", state.expose()));
+
+        let complete_uri = build_uri(&self.config.auth_url, &params)?;
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+        self.requested = Permission::dedup(permissions);
+        self.state = Some(state);
+        self.pkce = pkce;
+        self.redirect_uri = redirect_uri.to_string();
+        Ok(complete_uri)
+    }
+
+    /// Get code from authorization response uri.
+    /// Refused authorization (`error=access_denied`) is returned
+    /// as `Error::OAuth` and the status moves to `Failed`.
+    fn parse_response_code(&mut self, response: &str) -> Result<Secret, Error> {
+        match RedirectOutcome::parse(response) {
+            RedirectOutcome::Granted { code, state } => {
+                check_state(self.state.as_ref(), state)?;
+                self.tokens.transition(AuthorizationStatus::CodeReceived)?;
+                Ok(code)
+            }
+            RedirectOutcome::Denied { reason } => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            RedirectOutcome::Malformed(msg) => Err(Error::Parse(msg)),
+        }
+    }
+
+    /// Exchange the code for access and refresh token. With PKCE
+    /// the verifier of the last authorize link proves the code belongs
+    /// to us and the application secret can be empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::oauth2::{ClientAuth, GenericOAuth2, Provider};
+    /// use music_streamer::auth::{Authenticator, Permission, Secret, ServiceConfig, ServiceType};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// fn scopes(_: Permission) -> Option<&'static [&'static str]> {
+    ///     Some(&[])
+    /// }
+    ///
+    /// // (client auth, expected form, expected authorization header)
+    /// let cases = vec![
+    ///     (ClientAuth::Body, "grant_type=authorization_code&code=c\
+    ///                         &redirect_uri=http%3A%2F%2Flocalhost%2Fcb\
+    ///                         &client_id=111&client_secret=s%26", None),
+    ///     (ClientAuth::Basic, "grant_type=authorization_code&code=c\
+    ///                          &redirect_uri=http%3A%2F%2Flocalhost%2Fcb",
+    ///      Some("Basic MTExOnMm")),
+    /// ];
+    /// for (client_auth, form, header) in cases {
+    ///     let mock = MockTransport::new();
+    ///     mock.push_response(Response::new(200, r#"{"access_token":"token"}"#));
+    ///     let provider = Provider::new(ServiceType::Spotify, scopes)
+    ///         .with_client_auth(client_auth)
+    ///         .with_pkce(false);
+    ///     let config = ServiceConfig::new(ServiceType::Spotify)
+    ///         .with_token_url("http://127.0.0.1:8080/token");
+    ///
+    ///     let mut auth = GenericOAuth2::with_config(provider, config, Box::new(mock.clone()));
+    ///     auth.get_authorize_link("111", "http://localhost/cb", &[]).unwrap();
+    ///     auth.authenticate_application("111", &Secret::from("s&"), &Secret::from("c"))
+    ///         .unwrap();
+    ///
+    ///     assert_eq!(auth.get_token().unwrap().expose(), "token");
+    ///     let request = &mock.requests()[0];
+    ///     assert_eq!(String::from_utf8(request.body.clone()).unwrap(), form);
+    ///     let authorization = request.headers.iter()
+    ///         .find(|header| header.0 == "Authorization")
+    ///         .map(|header| &header.1[..]);
+    ///     assert_eq!(authorization, header);
+    /// }
+    /// ```
+    fn authenticate_application(&mut self, app_id: &str, app_secret: &Secret,
+                               code: &Secret) -> Result<(), Error> {
+        // code may be handed over without parse_response_code
+        self.tokens.transition(AuthorizationStatus::CodeReceived)?;
+        if self.state.is_none() {
+            return Err(Error::InvalidState("no authorization link was generated"))
+        }
+        let verifier = self.pkce.as_ref().map(|pkce| pkce.verifier.clone());
+
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code.expose()),
+            ("redirect_uri", &self.redirect_uri[..]),
+        ];
+        if let Some(ref verifier) = verifier {
+            params.push(("code_verifier", verifier.expose()));
+        }
+
+        let response = match self.request_token(&params, app_id, app_secret) {
+            Ok(response) => response,
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                return Err(err)
+            }
+        };
+
+        self.state = None;
+        self.pkce = None;
+        self.accept_token(response)
+    }
+
+    /// Save token to authentication object
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save token which stops being valid at `expires_at`.
+    /// `None` is used for tokens which never expire.
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        self.tokens.save(token, expires_at)
+    }
+
+    /// Get active user token
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get()
+    }
+
+    /// Check if there is a token which didn't expire yet
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// Get time when the token expires, `None` if it never expires
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.tokens.expires_at()
+    }
+
+    /// Get how long the token will be valid, `None` if it never expires
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.tokens.time_remaining()
+    }
+
+    /// Get permissions granted by the user to the application
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    /// Attach store and restore tokens of the account from it.
+    /// Expired token is restored when it can be refreshed, the status
+    /// is `Expired` until `refresh` is called.
+    /// Restored token abandons the authorization in progress.
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.state = None;
+        self.pkce = None;
+        self.tokens.attach_store(store, account)
+    }
+
+    /// Forget the tokens and delete them from the attached store
+    fn logout(&mut self) -> Result<(), Error> {
+        self.state = None;
+        self.pkce = None;
+        self.tokens.logout()
+    }
+
+    /// Revoke the token at the revoke endpoint (RFC 7009) when the service
+    /// has one and forget the tokens
+    fn revoke(&mut self) -> Result<(), Error> {
+        if !self.tokens.has_token() {
+            return self.logout()
+        }
+
+        let answer = match self.config.revoke_url {
+            Some(ref url) => {
+                let request = Request::post(url)
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(build_form(&[("token", self.tokens.get()?.expose())]));
+                revoke_answer(self.transport.send(&request))
+            }
+            None => Ok(()),
+        };
+
+        self.state = None;
+        self.pkce = None;
+        self.tokens.revoke(answer)
+    }
+
+    /// Get new access token with the refresh token, the service may
+    /// send new refresh token too. Refused refresh token moves
+    /// the status to `Failed`.
+    fn refresh(&mut self, app_id: &str, app_secret: &Secret) -> Result<(), Error> {
+        let refresh = match self.tokens.refresh_token() {
+            Some(refresh) => refresh.clone(),
+            None => return Err(Error::InvalidState("no refresh token was acquired")),
+        };
+
+        let response = self.request_token(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh.expose()),
+        ], app_id, app_secret);
+        let response = match response {
+            Ok(response) => response,
+            Err(Error::OAuth(reason)) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                return Err(Error::OAuth(reason))
+            }
+            Err(err) => return Err(err),
+        };
+
+        // scopes can't change by refreshing
+        self.requested = self.tokens.granted().to_vec();
+        self.accept_token(response)
+    }
+
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    /// Restore the session from the snapshot, the tokens are saved
+    /// to the attached store
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        self.tokens.import(snapshot)?;
+        self.state = None;
+        self.pkce = None;
+        Ok(())
+    }
+}
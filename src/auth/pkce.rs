@@ -0,0 +1,55 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Proof Key for Code Exchange (RFC 7636) used by the authorization
+//! code flow of applications which can't keep a secret.
+
+use super::random::random_string;
+use super::{Error, Secret};
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+
+/// Name of the challenge method sent in the authorize link
+pub const METHOD: &str = "S256";
+
+/// Number of random bytes in the verifier, 64 characters once encoded
+const VERIFIER_LENGTH: usize = 48;
+
+/// Verifier kept until the code is exchanged and the challenge
+/// sent in the authorize link
+pub struct Pkce {
+    pub verifier: Secret,
+    pub challenge: String,
+}
+
+impl Pkce {
+    /// Generate new random verifier and its challenge
+    pub fn new() -> Result<Pkce, Error> {
+        let verifier = Secret::new(random_string(VERIFIER_LENGTH)?);
+        let challenge = Pkce::challenge_of(verifier.expose());
+        Ok(Pkce { verifier, challenge })
+    }
+
+    /// Unpadded url safe base64 of SHA-256 of the verifier
+    pub fn challenge_of(verifier: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input_str(verifier);
+        let mut digest = [0u8; 32];
+        hasher.result(&mut digest);
+        digest.to_base64(URL_SAFE)
+    }
+}
@@ -0,0 +1,31 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Random values used in the authorization flows.
+
+use super::Error;
+
+use rand::{OsRng, Rng};
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+
+/// Generate `bytes` random bytes from the operating system
+/// and encode them as unpadded url safe base64
+pub fn random_string(bytes: usize) -> Result<String, Error> {
+    let mut rng = OsRng::new()
+        .map_err(|_| Error::InvalidState("random numbers are not available"))?;
+    let mut data = vec![0u8; bytes];
+    rng.fill_bytes(&mut data);
+    Ok(data.to_base64(URL_SAFE))
+}
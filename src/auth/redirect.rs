@@ -0,0 +1,371 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Handling of the browser redirect at the end of the user authorization.
+//!
+//! `RedirectOutcome` parses the redirect uri. `RedirectListener` is a local
+//! loopback listener which catches the redirect: bind the listener, pass its
+//! `redirect_uri()` to `get_authorize_link` and wait until the browser comes
+//! back with the code.
+
+use super::Authenticator;
+use super::Error;
+use super::Secret;
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use url::form_urlencoded;
+
+/// Page served to the browser after the redirect was received
+const CLOSE_PAGE: &str = "<!DOCTYPE html>\n<html><head><title>Authorization finished</title></head>\
+                          <body><p>Authorization finished, you can close this tab.</p></body></html>\n";
+
+/// How often the listener checks for the incoming connection
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// How long a single connection may take to send its request,
+/// browsers open connections they never use
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum size of the request head we are willing to read
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// Check that the state sent back by the service is the one sent
+/// in the authorize link
+pub(crate) fn check_state(expected: Option<&Secret>, received: Option<String>)
+                          -> Result<(), Error> {
+    match (expected, received.map(Secret::new)) {
+        (Some(expected), Some(received)) if *expected == received => Ok(()),
+        (None, _) => Err(Error::InvalidState("no authorization link was generated")),
+        _ => Err(Error::StateMismatch),
+    }
+}
+
+/// Result of the user authorization carried by the redirect uri
+#[derive(Debug, PartialEq)]
+pub enum RedirectOutcome {
+    /// User granted the access, code can be exchanged for token
+    Granted {
+        /// Authorization code
+        code: Secret,
+        /// State sent back by the service, if any
+        state: Option<String>,
+    },
+    /// User or service refused the access
+    Denied {
+        /// Reason sent by the service (`error_reason` or `error`)
+        reason: String,
+    },
+    /// Redirect doesn't contain any result of the authorization
+    Malformed(String),
+}
+
+impl RedirectOutcome {
+    //! Parsed authorization redirect.
+
+    /// Parse redirect uri. Full uris (`http://host/path?code=...`),
+    /// paths (`/path?code=...`) and bare query strings (`code=...`
+    /// or `?code=...`) are accepted, fragment is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::auth::redirect::RedirectOutcome;
+    ///
+    /// let granted = |code: &str, state: Option<&str>| RedirectOutcome::Granted {
+    ///     code: Secret::from(code),
+    ///     state: state.map(|state| state.to_string()),
+    /// };
+    /// let denied = |reason: &str| RedirectOutcome::Denied { reason: reason.to_string() };
+    ///
+    /// let cases = vec![
+    ///     ("http://example.com/cb?code=abc", granted("abc", None)),
+    ///     ("http://example.com/cb?code=x&state=y", granted("x", Some("y"))),
+    ///     ("http://example.com/cb?state=y&code=x#fragment", granted("x", Some("y"))),
+    ///     ("http://example.com/cb?code=a%2Bb%26c&state=s+t", granted("a+b&c", Some("s t"))),
+    ///     ("/cb?code=abc&other=1", granted("abc", None)),
+    ///     ("code=abc&state=y", granted("abc", Some("y"))),
+    ///     ("?code=abc", granted("abc", None)),
+    ///     ("http://example.com/cb?error_reason=user_denied", denied("user_denied")),
+    ///     ("error=access_denied&error_description=no", denied("access_denied")),
+    /// ];
+    /// for (uri, expected) in cases {
+    ///     assert_eq!(RedirectOutcome::parse(uri), expected, "{}", uri);
+    /// }
+    ///
+    /// let malformed = vec![
+    ///     "http://example.com/cb",
+    ///     "http://example.com/cb?state=y",
+    ///     "http://example.com/cb?code=",
+    ///     "http://example.com/cb?code=a&code=b",
+    ///     "",
+    /// ];
+    /// for uri in malformed {
+    ///     match RedirectOutcome::parse(uri) {
+    ///         RedirectOutcome::Malformed(_) => {}
+    ///         other => panic!("{} parsed as {:?}", uri, other),
+    ///     }
+    /// }
+    /// ```
+    pub fn parse(response: &str) -> RedirectOutcome {
+        RedirectOutcome::parse_with(response, "code")
+    }
+
+    /// Parse redirect uri of a service which sends the code
+    /// in another parameter than `code`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::Secret;
+    /// use music_streamer::auth::redirect::RedirectOutcome;
+    ///
+    /// assert_eq!(RedirectOutcome::parse_with("http://example.com/cb?state=s&token=t", "token"),
+    ///            RedirectOutcome::Granted {
+    ///                code: Secret::from("t"),
+    ///                state: Some("s".to_string()),
+    ///            });
+    /// match RedirectOutcome::parse_with("http://example.com/cb?code=c", "token") {
+    ///     RedirectOutcome::Malformed(_) => {}
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// ```
+    pub fn parse_with(response: &str, code_param: &str) -> RedirectOutcome {
+        let response = response.trim();
+        let response = response.split('#').next().unwrap_or("");
+
+        let query = match response.find('?') {
+            Some(x) => &response[x+1..],
+            // uri or path without any query
+            None if response.contains("://") || response.starts_with('/') => "",
+            None => response,
+        };
+
+        let mut code = None;
+        let mut state = None;
+        let mut reason = None;
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            let slot = match &key[..] {
+                name if name == code_param => &mut code,
+                "state" => &mut state,
+                "error_reason" | "error" => {
+                    // Deezer's error_reason is more specific than generic error
+                    if reason.is_none() || key == "error_reason" {
+                        reason = Some(value);
+                    }
+                    continue
+                }
+                _ => continue,
+            };
+
+            if slot.is_some() {
+                return RedirectOutcome::Malformed(format!("parameter '{}' is repeated", key))
+            }
+            *slot = Some(value);
+        }
+
+        if let Some(reason) = reason {
+            return RedirectOutcome::Denied { reason }
+        }
+
+        match code {
+            Some(ref code) if code.is_empty() => {
+                RedirectOutcome::Malformed(format!("{} is empty", code_param))
+            }
+            Some(code) => RedirectOutcome::Granted { code: Secret::new(code), state },
+            None => RedirectOutcome::Malformed(format!("no {} in the redirect", code_param)),
+        }
+    }
+}
+
+/// Listener bound to `127.0.0.1` waiting for a single authorization redirect
+pub struct RedirectListener {
+    listener: TcpListener,
+    path: String,
+}
+
+impl RedirectListener {
+    //! Loopback listener for the authorization redirect.
+
+    /// Bind listener to a free port chosen by the system.
+    /// Redirect is expected on the root path.
+    pub fn bind() -> Result<RedirectListener, Error> {
+        RedirectListener::bind_to(0, "/")
+    }
+
+    /// Bind listener to given port (0 picks a free one) and wait
+    /// for the redirect on given path
+    pub fn bind_to(port: u16, path: &str) -> Result<RedirectListener, Error> {
+        if !path.starts_with('/') {
+            return Err(Error::InvalidState("redirect path has to start with '/'"))
+        }
+
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(RedirectListener {
+            listener,
+            path: path.to_string(),
+        })
+    }
+
+    /// Port the listener is bound to
+    pub fn port(&self) -> Result<u16, Error> {
+        Ok(self.listener.local_addr()?.port())
+    }
+
+    /// Uri which should be used as `redirect_uri` in `get_authorize_link`
+    pub fn redirect_uri(&self) -> Result<String, Error> {
+        Ok(format!("http://127.0.0.1:{}{}", self.port()?, self.path))
+    }
+
+    /// Wait for the browser redirect and return the code from it.
+    /// The redirect is parsed by the authenticator which generated
+    /// the authorize link so its state is checked.
+    /// When user refused the access the reason is returned as `Error::OAuth`.
+    /// Requests on other paths (like `/favicon.ico`) are answered with 404
+    /// and ignored, so are connections which fail or stay silent.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// extern crate hyper;
+    /// extern crate music_streamer;
+    ///
+    /// use std::thread;
+    /// use std::time::Duration;
+    /// use music_streamer::auth::deezer::AuthDeezer;
+    /// use music_streamer::auth::redirect::RedirectListener;
+    /// use music_streamer::auth::{Authenticator, Permission};
+    ///
+    /// # fn main() {
+    /// let listener = RedirectListener::bind().unwrap();
+    /// let redirect_uri = listener.redirect_uri().unwrap();
+    ///
+    /// let mut auth = AuthDeezer::new();
+    /// let link = auth.get_authorize_link("111", &redirect_uri, &[Permission::BasicAccess])
+    ///     .unwrap();
+    /// let state = link.split("&state=").nth(1).unwrap().to_string();
+    ///
+    /// // browser is sent back by Deezer
+    /// let uri = format!("{}?code=fre54bf0a48d1bf566f24c2289ce06d1&state={}", redirect_uri, state);
+    /// let browser = thread::spawn(move || {
+    ///     hyper::Client::new().get(&uri).send().unwrap().status
+    /// });
+    ///
+    /// let code = listener.wait(&mut auth, Duration::from_secs(5)).unwrap();
+    /// assert_eq!(code.expose(), "fre54bf0a48d1bf566f24c2289ce06d1");
+    /// assert_eq!(browser.join().unwrap(), hyper::Ok);
+    /// # }
+    /// ```
+    pub fn wait(self, auth: &mut dyn Authenticator, timeout: Duration) -> Result<Secret, Error> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Some(target) = self.handle(stream, deadline) {
+                        let response = format!("http://127.0.0.1:{}{}", self.port()?, target);
+                        return auth.parse_response_code(&response)
+                    }
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err(Error::Transport("timed out waiting for the redirect".to_string()))
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+                Err(err) => return Err(Error::from(err)),
+            }
+        }
+    }
+
+    /// Read single request from the browser and answer it.
+    /// Returns request target of the redirect or `None` when the request
+    /// wasn't the redirect we wait for. Errors of the connection are
+    /// only logged, they must not end the wait.
+    fn handle(&self, mut stream: TcpStream, deadline: Instant) -> Option<String> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let timeout = Some(remaining.min(CONNECTION_TIMEOUT).max(POLL_INTERVAL));
+        let prepared = stream.set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(timeout))
+            .and_then(|_| stream.set_write_timeout(timeout));
+        if let Err(err) = prepared {
+            debug!("dropping connection while waiting for redirect: {}", err);
+            return None
+        }
+
+        let target = match read_request_target(&mut stream) {
+            Ok(target) => target,
+            Err(err) => {
+                debug!("dropping request while waiting for redirect: {}", err);
+                respond(&mut stream, "400 Bad Request", "Bad request\n");
+                return None
+            }
+        };
+
+        let path = match target.find('?') {
+            Some(x) => &target[..x],
+            None => &target[..],
+        };
+
+        if path != self.path {
+            debug!("ignoring request on {} while waiting for redirect", path);
+            respond(&mut stream, "404 Not Found", "Not found\n");
+            return None
+        }
+
+        debug!("authorization redirect received");
+        // the code is ours even when the browser went away
+        respond(&mut stream, "200 OK", CLOSE_PAGE);
+        Some(target)
+    }
+}
+
+/// Read request head and return the request target of `GET` request
+fn read_request_target(stream: &mut TcpStream) -> Result<String, Error> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf)?;
+        if read == 0 || head.len() + read > MAX_REQUEST_SIZE {
+            return Err(Error::Parse("incomplete request".to_string()))
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or("").split(' ');
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Ok(target.to_string()),
+        _ => Err(Error::Parse("unexpected request".to_string())),
+    }
+}
+
+/// Send simple HTTP response and close the connection
+fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\n\
+                            Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                           status, body.len(), body);
+    if let Err(err) = stream.write_all(response.as_bytes()).and_then(|_| stream.flush()) {
+        debug!("failed to answer the browser: {}", err);
+    }
+}
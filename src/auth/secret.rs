@@ -0,0 +1,155 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Wrapper for sensitive values like tokens, application secrets
+//! and authorization codes.
+
+use std::fmt;
+use std::ptr;
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use crypto::util::fixed_time_eq;
+use rustc_serialize::json::Json;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Sensitive value which is never printed and which memory
+/// is zeroed when it is dropped.
+/// The value is accessible only through `expose`.
+///
+/// Zeroing covers the `Secret` itself and the copies this library
+/// has to make: requests and responses of the HTTP transport, token
+/// store documents and snapshot JSON. Copies made by the caller from
+/// `expose`, by the HTTP stack behind the transport or by the JSON
+/// parser while reading are out of its reach.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::Secret;
+///
+/// let secret = Secret::from("token");
+/// assert_eq!(format!("{:?}", secret), "Secret(***)");
+/// assert_eq!(format!("{}", secret), "***");
+/// assert_eq!(secret.expose(), "token");
+/// ```
+#[derive(Clone, Default)]
+pub struct Secret(String);
+
+impl Secret {
+    //! Redacted sensitive value.
+
+    /// Wrap the value, it is moved so no other copy is left behind
+    pub fn new(value: String) -> Secret {
+        Secret(value)
+    }
+
+    /// Get the wrapped value
+    ///
+    /// DO NOT STORE THE VALUE ELSEWHERE
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Check if the value is empty
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Secret {
+        Secret::new(value)
+    }
+}
+
+impl<'a> From<&'a str> for Secret {
+    fn from(value: &'a str) -> Secret {
+        Secret::new(value.to_string())
+    }
+}
+
+impl PartialEq for Secret {
+    /// Values are compared in constant time
+    fn eq(&self, other: &Secret) -> bool {
+        self.0.len() == other.0.len() && fixed_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
+impl Eq for Secret {}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe_string(&mut self.0);
+    }
+}
+
+/// Zero the bytes and empty them. Whole allocation is zeroed,
+/// it can contain leftovers beyond the length.
+pub(crate) fn wipe_bytes(bytes: &mut Vec<u8>) {
+    unsafe {
+        let data = bytes.as_mut_ptr();
+        for i in 0..bytes.capacity() {
+            ptr::write_volatile(data.add(i), 0);
+        }
+        bytes.set_len(0);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Zero the string and empty it
+pub(crate) fn wipe_string(value: &mut String) {
+    unsafe {
+        wipe_bytes(value.as_mut_vec());
+    }
+}
+
+/// Zero every string value of the JSON, object keys are left alone
+pub(crate) fn wipe_json(json: &mut Json) {
+    match *json {
+        Json::String(ref mut value) => wipe_string(value),
+        Json::Array(ref mut items) => items.iter_mut().for_each(wipe_json),
+        Json::Object(ref mut object) => object.values_mut().for_each(wipe_json),
+        _ => {}
+    }
+}
+
+/// Secret is written as plain string, serialize it only into
+/// places which are as safe as the token store
+#[cfg(feature = "serde")]
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.expose())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Secret, D::Error> {
+        String::deserialize(deserializer).map(Secret::new)
+    }
+}
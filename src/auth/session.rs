@@ -0,0 +1,186 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Authenticators of several accounts and services kept together.
+
+use super::{Authenticator, AuthorizationStatus, Error, ServiceType};
+
+use std::collections::HashMap;
+
+/// Summary of one session returned by `SessionManager::sessions`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub service: ServiceType,
+    pub account: String,
+    pub status: AuthorizationStatus,
+    /// Session is the active one of its service
+    pub active: bool,
+}
+
+/// Authenticators identified by service and account id.
+/// Every service has at most one active account, the first
+/// account added to the service becomes active.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::{AuthorizationStatus, Secret, ServiceType, SessionManager};
+/// use music_streamer::auth::deezer::AuthDeezer;
+///
+/// let mut sessions = SessionManager::new();
+/// sessions.add(ServiceType::DEEZER, "alice", Box::new(AuthDeezer::new())).unwrap();
+/// sessions.add(ServiceType::DEEZER, "bob", Box::new(AuthDeezer::new())).unwrap();
+/// assert_eq!(sessions.active_account(ServiceType::DEEZER), Some("alice"));
+///
+/// sessions.set_active(ServiceType::DEEZER, "bob").unwrap();
+/// sessions.active_mut(ServiceType::DEEZER).unwrap().save_token(Secret::from("token")).unwrap();
+///
+/// let list = sessions.sessions();
+/// assert_eq!(list.len(), 2);
+/// assert_eq!((&list[0].account[..], list[0].status, list[0].active),
+///            ("alice", AuthorizationStatus::Nothing, false));
+/// assert_eq!((&list[1].account[..], list[1].status, list[1].active),
+///            ("bob", AuthorizationStatus::TokenAcquired, true));
+///
+/// sessions.logout(ServiceType::DEEZER, "bob").unwrap();
+/// assert!(sessions.get(ServiceType::DEEZER, "bob").unwrap().get_token().is_err());
+///
+/// sessions.remove(ServiceType::DEEZER, "bob").unwrap();
+/// assert!(sessions.active(ServiceType::DEEZER).is_none());
+/// assert!(sessions.set_active(ServiceType::DEEZER, "bob").is_err());
+/// ```
+#[derive(Default)]
+pub struct SessionManager {
+    sessions: HashMap<(ServiceType, String), Box<dyn Authenticator>>,
+    active: HashMap<ServiceType, String>,
+}
+
+impl SessionManager {
+    //! Manager of the sessions of all linked accounts.
+
+    /// Create manager without any session
+    pub fn new() -> SessionManager {
+        SessionManager::default()
+    }
+
+    /// Add authenticator of the account, fails when the account
+    /// of the service is already present
+    pub fn add(&mut self, service: ServiceType, account: &str, auth: Box<dyn Authenticator>)
+               -> Result<(), Error> {
+        let key = (service, account.to_string());
+        if self.sessions.contains_key(&key) {
+            return Err(Error::InvalidState("account is already added"))
+        }
+
+        self.sessions.insert(key, auth);
+        self.active.entry(service).or_insert_with(|| account.to_string());
+        Ok(())
+    }
+
+    /// Create authenticator of the service with default settings
+    /// and add it for the account
+    pub fn create(&mut self, service: ServiceType, account: &str)
+                  -> Result<&mut dyn Authenticator, Error> {
+        self.add(service, account, super::new(service)?)?;
+        self.get_mut(service, account)
+            .ok_or(Error::InvalidState("account was not added"))
+    }
+
+    /// Get authenticator of the account
+    pub fn get(&self, service: ServiceType, account: &str) -> Option<&dyn Authenticator> {
+        self.sessions.get(&(service, account.to_string())).map(|auth| &**auth)
+    }
+
+    /// Get mutable authenticator of the account
+    pub fn get_mut(&mut self, service: ServiceType, account: &str)
+                   -> Option<&mut dyn Authenticator> {
+        match self.sessions.get_mut(&(service, account.to_string())) {
+            Some(auth) => Some(&mut **auth),
+            None => None,
+        }
+    }
+
+    /// Make the account active one of its service
+    pub fn set_active(&mut self, service: ServiceType, account: &str) -> Result<(), Error> {
+        if !self.sessions.contains_key(&(service, account.to_string())) {
+            return Err(Error::InvalidState("unknown account"))
+        }
+
+        self.active.insert(service, account.to_string());
+        Ok(())
+    }
+
+    /// Get id of the active account of the service
+    pub fn active_account(&self, service: ServiceType) -> Option<&str> {
+        self.active.get(&service).map(|account| &account[..])
+    }
+
+    /// Get authenticator of the active account of the service
+    pub fn active(&self, service: ServiceType) -> Option<&dyn Authenticator> {
+        let account = self.active.get(&service)?;
+        self.get(service, account)
+    }
+
+    /// Get mutable authenticator of the active account of the service
+    pub fn active_mut(&mut self, service: ServiceType) -> Option<&mut dyn Authenticator> {
+        let account = self.active.get(&service)?.clone();
+        self.get_mut(service, &account)
+    }
+
+    /// List all sessions sorted by service and account
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut list: Vec<SessionInfo> = self.sessions.iter().map(|(key, auth)| {
+            SessionInfo {
+                service: key.0,
+                account: key.1.clone(),
+                status: auth.status(),
+                active: self.active.get(&key.0) == Some(&key.1),
+            }
+        }).collect();
+
+        list.sort_by(|a, b| (a.service.name(), &a.account).cmp(&(b.service.name(), &b.account)));
+        list
+    }
+
+    /// Log out the account without contacting the service,
+    /// the session stays in the manager
+    pub fn logout(&mut self, service: ServiceType, account: &str) -> Result<(), Error> {
+        match self.get_mut(service, account) {
+            Some(auth) => auth.logout(),
+            None => Err(Error::InvalidState("unknown account")),
+        }
+    }
+
+    /// Revoke token of the account at the service,
+    /// the session stays in the manager
+    pub fn revoke(&mut self, service: ServiceType, account: &str) -> Result<(), Error> {
+        match self.get_mut(service, account) {
+            Some(auth) => auth.revoke(),
+            None => Err(Error::InvalidState("unknown account")),
+        }
+    }
+
+    /// Remove session of the account and return its authenticator.
+    /// Token is kept in the attached store, log out first to delete it.
+    pub fn remove(&mut self, service: ServiceType, account: &str)
+                  -> Option<Box<dyn Authenticator>> {
+        let removed = self.sessions.remove(&(service, account.to_string()));
+        if self.active_account(service) == Some(account) {
+            self.active.remove(&service);
+        }
+
+        removed
+    }
+}
@@ -0,0 +1,270 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Versioned snapshot of a session used to move the session
+//! between processes.
+
+use super::{wipe_json, AuthorizationStatus, Error, Permission, Secret, ServiceType};
+#[cfg(feature = "serde")]
+use super::Authenticator;
+use super::store::{from_unix, to_unix};
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use rustc_serialize::json::Json;
+#[cfg(feature = "serde")]
+use serde::de::Error as DeError;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of the snapshot format written by this library
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Statuses by their names used in the JSON format
+const STATUSES: &[AuthorizationStatus] = &[
+    AuthorizationStatus::Nothing,
+    AuthorizationStatus::UserAuthentication,
+    AuthorizationStatus::CodeReceived,
+    AuthorizationStatus::TokenAcquired,
+    AuthorizationStatus::AuthorizationCompleted,
+    AuthorizationStatus::Expired,
+    AuthorizationStatus::Revoked,
+    AuthorizationStatus::Failed,
+];
+
+/// Session exported by `Authenticator::export_snapshot`.
+///
+/// JSON format written by `to_json`, version 1:
+///
+/// ```text
+/// {
+///   "version": 1,
+///   "service": "deezer",
+///   "status": "AuthorizationCompleted",
+///   "permissions": ["basic_access", "email"],
+///   "expires_at": 1500000000,    // seconds since unix epoch, null if it never expires
+///   "token": "...",              // only when secrets were included
+///   "refresh_token": "..."       // only when secrets were included and the service issued it
+/// }
+/// ```
+///
+/// Snapshots of newer versions are refused. With the `serde` feature
+/// the snapshot can be written to any format supported by serde, fields
+/// are the same as in the JSON above, missing tokens are left out.
+///
+/// Authenticators implement serde traits too, they are written as
+/// `export_snapshot(false)` so a serde round-trip of an authenticator
+/// doesn't keep the token. Serialize `export_snapshot(true)` to move
+/// the session with its token.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::deezer::AuthDeezer;
+/// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret, SessionSnapshot};
+///
+/// let mut cli = AuthDeezer::new();
+/// cli.save_token(Secret::from("token")).unwrap();
+///
+/// // secrets are left out unless asked for
+/// let public = cli.export_snapshot(false).to_json();
+/// assert!(!public.expose().contains("token\""));
+///
+/// let json = cli.export_snapshot(true).to_json();
+/// let mut daemon = AuthDeezer::new();
+/// daemon.import_snapshot(&SessionSnapshot::from_json(json.expose()).unwrap()).unwrap();
+/// assert_eq!(daemon.get_token().unwrap().expose(), "token");
+/// assert_eq!(daemon.status(), AuthorizationStatus::TokenAcquired);
+///
+/// let newer = json.expose().replace("\"version\":1", "\"version\":2");
+/// assert!(SessionSnapshot::from_json(&newer).is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SessionSnapshot {
+    /// Version of the format, `SNAPSHOT_VERSION` for new snapshots
+    pub version: u32,
+    pub service: ServiceType,
+    pub status: AuthorizationStatus,
+    /// Permissions granted with the token
+    pub permissions: Vec<Permission>,
+    /// Time when the token expires, `None` if it never expires
+    #[cfg_attr(feature = "serde", serde(with = "unix_seconds", default))]
+    pub expires_at: Option<SystemTime>,
+    /// Token, `None` unless secrets were included
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none", default))]
+    pub token: Option<Secret>,
+    /// Refresh token, `None` unless secrets were included
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none", default))]
+    pub refresh_token: Option<Secret>,
+}
+
+/// Write authenticator as its snapshot without secrets,
+/// used by `Serialize` of the authenticators
+#[cfg(feature = "serde")]
+pub(crate) fn serialize_authenticator<A, S>(auth: &A, serializer: S) -> Result<S::Ok, S::Error>
+    where A: Authenticator, S: Serializer
+{
+    auth.export_snapshot(false).serialize(serializer)
+}
+
+/// Read snapshot and import it into authenticator created for its service,
+/// used by `Deserialize` of the authenticators
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_authenticator<'de, A, D, F>(deserializer: D, create: F)
+                                                      -> Result<A, D::Error>
+    where A: Authenticator, D: Deserializer<'de>, F: FnOnce(ServiceType) -> Option<A>
+{
+    let snapshot = SessionSnapshot::deserialize(deserializer)?;
+    let mut auth = create(snapshot.service).ok_or_else(|| {
+        D::Error::custom(format!("{} snapshot can't be imported here", snapshot.service.name()))
+    })?;
+    auth.import_snapshot(&snapshot).map_err(D::Error::custom)?;
+    Ok(auth)
+}
+
+/// Expiry is written by serde as seconds since unix epoch, the same as by `to_json`
+#[cfg(feature = "serde")]
+mod unix_seconds {
+    use super::super::store::{from_unix, to_unix};
+
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &Option<SystemTime>, serializer: S)
+                                    -> Result<S::Ok, S::Error> {
+        match *time {
+            Some(time) => serializer.serialize_some(&to_unix(time)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+                                                  -> Result<Option<SystemTime>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(from_unix))
+    }
+}
+
+impl SessionSnapshot {
+    //! Session snapshot and its JSON format.
+
+    /// Check that the snapshot can be imported by the service authenticator
+    pub fn check(&self, service: ServiceType) -> Result<(), Error> {
+        if self.version > SNAPSHOT_VERSION {
+            return Err(Error::Parse(format!("unsupported snapshot version {}", self.version)))
+        }
+        if self.service != service {
+            return Err(Error::Parse(format!("snapshot belongs to {}", self.service.name())))
+        }
+
+        Ok(())
+    }
+
+    /// Write snapshot as JSON, it is kept in `Secret` because
+    /// it can contain the token
+    pub fn to_json(&self) -> Secret {
+        let mut object = BTreeMap::new();
+        object.insert("version".to_string(), Json::U64(u64::from(self.version)));
+        object.insert("service".to_string(), Json::String(self.service.name().to_string()));
+        object.insert("status".to_string(), Json::String(format!("{:?}", self.status)));
+        object.insert("permissions".to_string(), Json::Array(
+            self.permissions.iter().map(|perm| Json::String(perm.as_str().to_string())).collect()
+        ));
+        object.insert("expires_at".to_string(), match self.expires_at {
+            Some(expires) => Json::U64(to_unix(expires)),
+            None => Json::Null,
+        });
+        if let Some(ref token) = self.token {
+            object.insert("token".to_string(), Json::String(token.expose().to_string()));
+        }
+        if let Some(ref refresh) = self.refresh_token {
+            object.insert("refresh_token".to_string(),
+                          Json::String(refresh.expose().to_string()));
+        }
+
+        let mut json = Json::Object(object);
+        let text = json.to_string();
+        wipe_json(&mut json);
+        Secret::new(text)
+    }
+
+    /// Read snapshot written by `to_json`
+    pub fn from_json(json: &str) -> Result<SessionSnapshot, Error> {
+        let mut json = Json::from_str(json)
+            .map_err(|err| Error::Parse(format!("invalid snapshot: {}", err)))?;
+        let snapshot = SessionSnapshot::from_object(&json);
+        wipe_json(&mut json);
+        snapshot
+    }
+
+    /// Read snapshot from parsed JSON
+    fn from_object(json: &Json) -> Result<SessionSnapshot, Error> {
+        let field = |name: &str| {
+            json.find(name).ok_or_else(|| Error::Parse(format!("snapshot is missing {}", name)))
+        };
+        let string = |name: &str| {
+            field(name)?.as_string()
+                .ok_or_else(|| Error::Parse(format!("invalid snapshot {}", name)))
+        };
+
+        let version = field("version")?.as_u64()
+            .ok_or_else(|| Error::Parse("invalid snapshot version".to_string()))?;
+        if version > u64::from(SNAPSHOT_VERSION) {
+            return Err(Error::Parse(format!("unsupported snapshot version {}", version)))
+        }
+
+        let status = string("status")?;
+        let status = STATUSES.iter()
+            .find(|known| format!("{:?}", known) == status)
+            .cloned()
+            .ok_or_else(|| Error::Parse(format!("unknown status '{}'", status)))?;
+
+        let mut permissions = Vec::new();
+        for perm in field("permissions")?.as_array()
+                .ok_or_else(|| Error::Parse("invalid snapshot permissions".to_string()))? {
+            let name = perm.as_string()
+                .ok_or_else(|| Error::Parse("invalid snapshot permission".to_string()))?;
+            permissions.push(name.parse()?);
+        }
+
+        let expires_at = match json.find("expires_at") {
+            None | Some(&Json::Null) => None,
+            Some(expires) => Some(from_unix(expires.as_u64().ok_or_else(|| {
+                Error::Parse("invalid snapshot expires_at".to_string())
+            })?)),
+        };
+
+        let token = match json.find("token") {
+            None => None,
+            Some(_) => Some(Secret::from(string("token")?)),
+        };
+        let refresh_token = match json.find("refresh_token") {
+            None => None,
+            Some(_) => Some(Secret::from(string("refresh_token")?)),
+        };
+
+        Ok(SessionSnapshot {
+            version: version as u32,
+            service: string("service")?.parse()?,
+            status,
+            permissions,
+            expires_at,
+            token,
+            refresh_token,
+        })
+    }
+}
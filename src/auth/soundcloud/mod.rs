@@ -0,0 +1,159 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! SoundCloud authorization. SoundCloud follows OAuth 2.1 so the work
+//! is done by `GenericOAuth2`: the authorization code flow with PKCE for
+//! users and the client credentials grant for public-only access.
+//! The application secret is sent in `Authorization: Basic` header.
+//!
+//! # Examples
+//!
+//! ```
+//! use music_streamer::auth::soundcloud;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission, Secret};
+//! use music_streamer::auth::{ServiceConfig, ServiceType};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"access_token":"token","expires_in":3599,
+//!     "refresh_token":"refresh","scope":"","token_type":"bearer"}"#));
+//! mock.push_response(Response::new(200, r#"{"access_token":"new","expires_in":3599,
+//!     "refresh_token":"new-refresh","scope":""}"#));
+//! let config = ServiceConfig::new(ServiceType::SoundCloud)
+//!     .with_token_url("http://127.0.0.1:8080/oauth/token");
+//!
+//! let mut auth = soundcloud::with_config(config, Box::new(mock.clone()));
+//! let link = auth.get_authorize_link("111", "http://localhost/cb",
+//!                                    &[Permission::BasicAccess, Permission::ManageLibrary,
+//!                                      Permission::OfflineAccess, Permission::Email])
+//!     .unwrap();
+//! assert!(link.starts_with("https://secure.soundcloud.com/authorize?client_id=111\
+//!                           &response_type=code&redirect_uri=http%3A%2F%2Flocalhost%2Fcb\
+//!                           &code_challenge_method=S256&code_challenge="));
+//!
+//! let state = link.split("&state=").nth(1).unwrap();
+//! let code = auth.parse_response_code(&format!("http://localhost/cb?code=c&state={}", state))
+//!     .unwrap();
+//! auth.authenticate_application("111", &Secret::from("secret"), &code).unwrap();
+//!
+//! assert_eq!(auth.get_token().unwrap().expose(), "token");
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//! // email can't be granted by SoundCloud
+//! assert_eq!(auth.granted_permissions(), &[Permission::BasicAccess, Permission::ManageLibrary,
+//!                                          Permission::OfflineAccess]);
+//!
+//! let request = &mock.requests()[0];
+//! let body = String::from_utf8(request.body.clone()).unwrap();
+//! assert!(body.starts_with("grant_type=authorization_code&code=c\
+//!                           &redirect_uri=http%3A%2F%2Flocalhost%2Fcb&code_verifier="));
+//! assert!(request.headers.contains(&("Authorization".to_string(),
+//!                                    "Basic MTExOnNlY3JldA==".to_string())));
+//!
+//! auth.refresh("111", &Secret::from("secret")).unwrap();
+//! assert_eq!(auth.get_token().unwrap().expose(), "new");
+//! assert_eq!(String::from_utf8(mock.requests()[1].body.clone()).unwrap(),
+//!            "grant_type=refresh_token&refresh_token=refresh");
+//! ```
+//!
+//! Public data can be read with the token of the application:
+//!
+//! ```
+//! use music_streamer::auth::soundcloud;
+//! use music_streamer::auth::{Authenticator, Error, Secret, ServiceConfig, ServiceType};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"access_token":"app","expires_in":3599}"#));
+//! mock.push_response(Response::new(401, r#"{"error":"invalid_client"}"#));
+//! let config = ServiceConfig::new(ServiceType::SoundCloud)
+//!     .with_token_url("http://127.0.0.1:8080/oauth/token");
+//!
+//! let mut auth = soundcloud::with_config(config, Box::new(mock.clone()));
+//! auth.authenticate_client("111", &Secret::from("secret")).unwrap();
+//! assert_eq!(auth.get_token().unwrap().expose(), "app");
+//! assert!(auth.granted_permissions().is_empty());
+//!
+//! match auth.authenticate_client("111", &Secret::from("wrong")) {
+//!     Err(Error::OAuth(reason)) => assert_eq!(reason, "invalid_client"),
+//!     other => panic!("unexpected {:?}", other),
+//! }
+//! ```
+
+use super::oauth2::{ClientAuth, GenericOAuth2, Provider};
+use super::Permission;
+use super::ServiceConfig;
+use super::ServiceType;
+use http::HttpTransport;
+
+/// SoundCloud authenticator
+pub type AuthSoundCloud = GenericOAuth2;
+
+/// Create new SoundCloud authentication object
+pub fn new() -> AuthSoundCloud {
+    GenericOAuth2::new(provider())
+}
+
+/// Create new SoundCloud authentication object which sends
+/// all requests by given transport
+pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthSoundCloud {
+    GenericOAuth2::with_transport(provider(), transport)
+}
+
+/// Create new SoundCloud authentication object using given endpoints
+/// and transport
+pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>) -> AuthSoundCloud {
+    GenericOAuth2::with_config(provider(), config, transport)
+}
+
+/// SoundCloud flavour of OAuth 2.0
+pub fn provider() -> Provider {
+    Provider::new(ServiceType::SoundCloud, scopes)
+        .with_client_auth(ClientAuth::Basic)
+}
+
+/// Official SoundCloud endpoints. SoundCloud signs out by a JSON
+/// request which isn't RFC 7009, tokens are only forgotten on revoke.
+pub fn default_config() -> ServiceConfig {
+    ServiceConfig {
+        auth_url: "https://secure.soundcloud.com/authorize".to_string(),
+        token_url: "https://secure.soundcloud.com/oauth/token".to_string(),
+        api_url: "https://api.soundcloud.com".to_string(),
+        revoke_url: None,
+    }
+}
+
+/// SoundCloud scopes needed for the permission.
+/// SoundCloud has only the default scope giving the application everything
+/// the user can do through the API, so no permission needs a scope. Refresh
+/// token is always issued which covers `OfflineAccess`. The API shows
+/// neither the email nor the listening history, those are never granted.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::Permission;
+/// use music_streamer::auth::soundcloud;
+///
+/// assert_eq!(soundcloud::scopes(Permission::ManageLibrary), Some(&[][..]));
+/// assert_eq!(soundcloud::scopes(Permission::Email), None);
+/// assert_eq!(soundcloud::scopes(Permission::ListeningHistory), None);
+/// ```
+pub fn scopes(permission: Permission) -> Option<&'static [&'static str]> {
+    match permission {
+        Permission::BasicAccess | Permission::OfflineAccess | Permission::ManageLibrary |
+        Permission::ManageCommunity | Permission::DeleteLibrary => Some(&[]),
+        Permission::Email | Permission::ListeningHistory => None,
+    }
+}
@@ -0,0 +1,198 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Spotify authorization. Spotify follows OAuth 2.0 so the work is done
+//! by `GenericOAuth2` using the authorization code flow with PKCE,
+//! the application secret is never needed.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate crypto;
+//! extern crate music_streamer;
+//! extern crate rustc_serialize;
+//! # fn main() {
+//! use crypto::digest::Digest;
+//! use crypto::sha2::Sha256;
+//! use rustc_serialize::base64::{ToBase64, URL_SAFE};
+//! use music_streamer::auth::spotify;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Permission, Secret};
+//! use music_streamer::auth::{ServiceConfig, ServiceType};
+//! use music_streamer::http::{Method, MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"access_token":"token","token_type":"Bearer",
+//!     "scope":"user-read-private","expires_in":3600,"refresh_token":"refresh"}"#));
+//! let config = ServiceConfig::new(ServiceType::Spotify)
+//!     .with_token_url("http://127.0.0.1:8080/api/token");
+//!
+//! let mut auth = spotify::with_config(config, Box::new(mock.clone()));
+//! let link = auth.get_authorize_link("111", "http://localhost:8080/cb",
+//!                                    &[Permission::BasicAccess, Permission::Email,
+//!                                      Permission::OfflineAccess]).unwrap();
+//! assert!(link.starts_with("https://accounts.spotify.com/authorize?client_id=111\
+//!                           &response_type=code\
+//!                           &redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcb\
+//!                           &scope=user-read-private+user-read-email\
+//!                           &code_challenge_method=S256&code_challenge="));
+//!
+//! let state = link.split("&state=").nth(1).unwrap();
+//! let code = auth.parse_response_code(&format!("http://localhost:8080/cb?code=co+de&state={}",
+//!                                              state)).unwrap();
+//! auth.authenticate_application("111", &Secret::default(), &code).unwrap();
+//!
+//! assert_eq!(auth.get_token().unwrap().expose(), "token");
+//! assert!(auth.time_remaining().unwrap().unwrap().as_secs() > 3500);
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//! // user unchecked the email scope
+//! assert_eq!(auth.granted_permissions(),
+//!            &[Permission::BasicAccess, Permission::OfflineAccess]);
+//!
+//! let request = &mock.requests()[0];
+//! assert_eq!(request.method, Method::Post);
+//! assert_eq!(request.url, "http://127.0.0.1:8080/api/token");
+//! let body = String::from_utf8(request.body.clone()).unwrap();
+//! assert!(body.starts_with("grant_type=authorization_code&code=co+de\
+//!                           &redirect_uri=http%3A%2F%2Flocalhost%3A8080%2Fcb\
+//!                           &code_verifier="));
+//! assert!(body.ends_with("&client_id=111"));
+//!
+//! // verifier matches the challenge sent in the link
+//! let verifier = body.split("&code_verifier=").nth(1).unwrap().split('&').next().unwrap();
+//! let mut sha = Sha256::new();
+//! sha.input_str(verifier);
+//! let mut digest = [0u8; 32];
+//! sha.result(&mut digest);
+//! let challenge = link.split("&code_challenge=").nth(1).unwrap().split('&').next().unwrap();
+//! assert_eq!(digest.to_base64(URL_SAFE), challenge);
+//!
+//! // refused code
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(400, r#"{"error":"invalid_grant",
+//!     "error_description":"Invalid authorization code"}"#));
+//! let mut auth = spotify::with_transport(Box::new(mock));
+//! auth.get_authorize_link("111", "http://localhost/cb", &[Permission::Email]).unwrap();
+//! match auth.authenticate_application("111", &Secret::default(), &Secret::from("c")) {
+//!     Err(Error::OAuth(reason)) => assert_eq!(reason, "Invalid authorization code"),
+//!     other => panic!("unexpected {:?}", other),
+//! }
+//! assert_eq!(auth.status(), AuthorizationStatus::Failed);
+//! # }
+//! ```
+//!
+//! Tokens are refreshed when they run out:
+//!
+//! ```
+//! use std::time::{Duration, SystemTime};
+//! use music_streamer::auth::spotify;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission, Secret};
+//! use music_streamer::auth::ServiceType;
+//! use music_streamer::auth::store::{MemoryStore, StoredToken, TokenStore};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let store = MemoryStore::new();
+//! store.save(ServiceType::Spotify, "user", &StoredToken {
+//!     token: Secret::from("old"),
+//!     expires_at: Some(SystemTime::now() - Duration::from_secs(1)),
+//!     permissions: vec![Permission::BasicAccess],
+//!     refresh_token: Some(Secret::from("refresh")),
+//! }).unwrap();
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"access_token":"new","expires_in":3600}"#));
+//! let mut auth = spotify::with_transport(Box::new(mock.clone()));
+//! // expired token is restored because it can be refreshed, but it isn't valid
+//! assert_eq!(auth.attach_store(Box::new(store.clone()), "user").unwrap(), false);
+//! assert_eq!(auth.status(), AuthorizationStatus::Expired);
+//!
+//! auth.refresh("111", &Secret::default()).unwrap();
+//! assert_eq!(auth.get_token().unwrap().expose(), "new");
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//! assert!(auth.has_permission(Permission::BasicAccess));
+//! assert_eq!(String::from_utf8(mock.requests()[0].body.clone()).unwrap(),
+//!            "grant_type=refresh_token&refresh_token=refresh&client_id=111");
+//!
+//! // old refresh token is kept
+//! let stored = store.load(ServiceType::Spotify, "user").unwrap().unwrap();
+//! assert_eq!(stored.token.expose(), "new");
+//! assert_eq!(stored.refresh_token.unwrap().expose(), "refresh");
+//! ```
+
+use super::oauth2::{GenericOAuth2, Provider};
+use super::Permission;
+use super::ServiceConfig;
+use super::ServiceType;
+use http::HttpTransport;
+
+/// Spotify authenticator
+pub type AuthSpotify = GenericOAuth2;
+
+/// Create new Spotify authentication object
+pub fn new() -> AuthSpotify {
+    GenericOAuth2::new(provider())
+}
+
+/// Create new Spotify authentication object which sends
+/// all requests by given transport
+pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthSpotify {
+    GenericOAuth2::with_transport(provider(), transport)
+}
+
+/// Create new Spotify authentication object using given endpoints
+/// and transport
+pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>) -> AuthSpotify {
+    GenericOAuth2::with_config(provider(), config, transport)
+}
+
+/// Spotify flavour of OAuth 2.0
+pub fn provider() -> Provider {
+    Provider::new(ServiceType::Spotify, scopes)
+}
+
+/// Official Spotify endpoints, Spotify can't revoke tokens
+pub fn default_config() -> ServiceConfig {
+    ServiceConfig {
+        auth_url: "https://accounts.spotify.com/authorize".to_string(),
+        token_url: "https://accounts.spotify.com/api/token".to_string(),
+        api_url: "https://api.spotify.com/v1".to_string(),
+        revoke_url: None,
+    }
+}
+
+/// Spotify scopes needed for the permission.
+/// `OfflineAccess` needs no scope, Spotify always issues refresh token.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::auth::Permission;
+/// use music_streamer::auth::spotify;
+///
+/// assert_eq!(spotify::scopes(Permission::Email), Some(&["user-read-email"][..]));
+/// assert_eq!(spotify::scopes(Permission::OfflineAccess), Some(&[][..]));
+/// ```
+pub fn scopes(permission: Permission) -> Option<&'static [&'static str]> {
+    let scopes: &'static [&'static str] = match permission {
+        Permission::BasicAccess => &["user-read-private"],
+        Permission::Email => &["user-read-email"],
+        Permission::OfflineAccess => &[],
+        Permission::ManageLibrary => &["user-library-read", "user-library-modify",
+                                       "playlist-modify-private", "playlist-modify-public"],
+        Permission::ManageCommunity => &["user-follow-read", "user-follow-modify"],
+        Permission::DeleteLibrary => &["user-library-modify"],
+        Permission::ListeningHistory => &["user-read-recently-played", "user-top-read"],
+    };
+    Some(scopes)
+}
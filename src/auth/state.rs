@@ -0,0 +1,102 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! State machine of the authorization shared by all authenticators.
+
+use super::AuthorizationStatus;
+use super::Error;
+
+use std::cell::{Cell, RefCell};
+
+/// Callback receiving every status change as (old, new) status
+pub type StatusObserver = Box<dyn FnMut(AuthorizationStatus, AuthorizationStatus) + Send>;
+
+/// Current authorization status which can be changed only by valid transitions.
+/// Expiry is found out while the status is read so the status can be changed
+/// through shared reference too.
+pub struct StatusMachine {
+    status: Cell<AuthorizationStatus>,
+    observers: RefCell<Vec<StatusObserver>>,
+}
+
+impl StatusMachine {
+    //! Authorization state machine.
+
+    /// Create machine in `Nothing` status
+    pub fn new() -> StatusMachine {
+        StatusMachine {
+            status: Cell::new(AuthorizationStatus::Nothing),
+            observers: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Get current status
+    pub fn get(&self) -> AuthorizationStatus {
+        self.status.get()
+    }
+
+    /// Check if the machine can go from one status to another
+    pub fn is_allowed(from: AuthorizationStatus, to: AuthorizationStatus) -> bool {
+        use super::AuthorizationStatus::*;
+
+        match (from, to) {
+            // authorization can be started again, abandoned or fail at any time
+            (_, UserAuthentication) | (_, Nothing) | (_, Failed) => true,
+            (UserAuthentication, CodeReceived) => true,
+            // token can be handed over directly (saved by hand, implicit flow),
+            // replaced or refreshed, restores start over from Nothing
+            (Nothing, TokenAcquired) | (UserAuthentication, TokenAcquired) |
+            (CodeReceived, TokenAcquired) | (TokenAcquired, TokenAcquired) |
+            (Expired, TokenAcquired) => true,
+            // token is verified
+            (TokenAcquired, AuthorizationCompleted) => true,
+            (TokenAcquired, Expired) | (AuthorizationCompleted, Expired) => true,
+            (TokenAcquired, Revoked) | (AuthorizationCompleted, Revoked) |
+            (Expired, Revoked) => true,
+            _ => false,
+        }
+    }
+
+    /// Move to another status and let the observers know.
+    /// Staying in the same status is not a change.
+    pub fn transition(&self, to: AuthorizationStatus) -> Result<(), Error> {
+        let from = self.status.get();
+        if from == to {
+            return Ok(())
+        }
+
+        if !StatusMachine::is_allowed(from, to) {
+            return Err(Error::InvalidTransition(from, to))
+        }
+
+        self.status.set(to);
+        debug!("authorization status changed from {:?} to {:?}", from, to);
+        for observer in self.observers.borrow_mut().iter_mut() {
+            observer(from, to);
+        }
+        Ok(())
+    }
+
+    /// Register callback which receives every status change
+    pub fn subscribe(&self, observer: StatusObserver) {
+        self.observers.borrow_mut().push(observer);
+    }
+}
+
+impl Default for StatusMachine {
+    fn default() -> StatusMachine {
+        StatusMachine::new()
+    }
+}
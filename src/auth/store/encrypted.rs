@@ -0,0 +1,263 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Store keeping tokens in a file encrypted by a key derived from passphrase.
+//!
+//! File starts with a header:
+//!
+//! | bytes | content                                    |
+//! |-------|--------------------------------------------|
+//! | 4     | magic `MSTE`                               |
+//! | 1     | format version, currently 1                |
+//! | 4     | PBKDF2-HMAC-SHA256 iterations, big endian  |
+//! | 16    | salt of the key derivation                 |
+//! | 8     | nonce of the cipher                        |
+//!
+//! followed by the same JSON document as `JsonFileStore` uses, encrypted
+//! by ChaCha20-Poly1305, and 16 bytes of authentication tag. The header is
+//! authenticated too so any change of the file is detected. Iterations
+//! are read before the header can be authenticated, files with iterations
+//! outside `1..=10_000_000` are rejected without deriving the key.
+
+use super::{delete_from_document, load_from_document, save_to_document};
+use super::{write_private_file, Document, StoredToken, TokenStore};
+use super::super::{wipe_bytes, Error, Secret, ServiceType};
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::hmac::Hmac;
+use crypto::pbkdf2::pbkdf2;
+use crypto::sha2::Sha256;
+use rand::{OsRng, Rng};
+
+const MAGIC: &[u8] = b"MSTE";
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 4 + 1 + 4 + SALT_LEN + NONCE_LEN;
+
+/// Default number of PBKDF2 iterations
+const DEFAULT_ITERATIONS: u32 = 100_000;
+
+/// Highest number of PBKDF2 iterations accepted, more would let
+/// a tampered file block `load` for hours
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Store keeping tokens in a file encrypted with passphrase.
+/// The file is created with `0600` permissions.
+///
+/// # Examples
+///
+/// ```
+/// use std::env;
+/// use std::fs;
+/// use std::time::{Duration, UNIX_EPOCH};
+/// use music_streamer::auth::deezer::AuthDeezer;
+/// use music_streamer::auth::{Authenticator, Secret, ServiceType};
+/// use music_streamer::auth::store::{EncryptedFileStore, TokenStore};
+///
+/// let path = env::temp_dir().join("music_streamer_encrypted_store_doc.bin");
+/// let store = EncryptedFileStore::with_iterations(&path, Secret::from("passphrase"), 1000)
+///     .unwrap();
+/// let expires = UNIX_EPOCH + Duration::from_secs(4000000000);
+///
+/// let mut auth = AuthDeezer::new();
+/// auth.attach_store(Box::new(store.clone()), "user").unwrap();
+/// auth.save_token_with_expiry(Secret::from("token"), Some(expires)).unwrap();
+///
+/// // token is restored after restart
+/// let mut auth = AuthDeezer::new();
+/// assert!(auth.attach_store(Box::new(store), "user").unwrap());
+/// assert_eq!(auth.get_token().unwrap().expose(), "token");
+/// assert_eq!(auth.expires_at().unwrap(), Some(expires));
+///
+/// // wrong passphrase can't read the file
+/// let wrong = EncryptedFileStore::new(&path, Secret::from("wrong"));
+/// assert!(wrong.load(ServiceType::DEEZER, "user").is_err());
+///
+/// // tampered file is rejected
+/// let mut data = fs::read(&path).unwrap();
+/// let last = data.len() - 1;
+/// data[last] ^= 1;
+/// fs::write(&path, data).unwrap();
+/// let store = EncryptedFileStore::with_iterations(&path, Secret::from("passphrase"), 1000)
+///     .unwrap();
+/// assert!(store.load(ServiceType::DEEZER, "user").is_err());
+///
+/// // so is a header asking for no or endless key derivation
+/// for iterations in &[[0u8, 0, 0, 0], [0xff, 0xff, 0xff, 0xff]] {
+///     let mut data = fs::read(&path).unwrap();
+///     data[5..9].copy_from_slice(iterations);
+///     fs::write(&path, data).unwrap();
+///     assert!(store.load(ServiceType::DEEZER, "user").is_err());
+/// }
+/// # fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    passphrase: Secret,
+    iterations: u32,
+}
+
+impl EncryptedFileStore {
+    //! Token store backed by encrypted file.
+
+    /// Create store using file on given path encrypted with the passphrase.
+    /// File is created with first saved token.
+    pub fn new<P: AsRef<Path>>(path: P, passphrase: Secret) -> EncryptedFileStore {
+        EncryptedFileStore {
+            path: path.as_ref().to_path_buf(),
+            passphrase,
+            iterations: DEFAULT_ITERATIONS,
+        }
+    }
+
+    /// Create store which derives the key with given number of iterations.
+    /// Iterations are saved in the file so it can be read by any store
+    /// with the right passphrase. Iterations have to be in `1..=10_000_000`.
+    pub fn with_iterations<P: AsRef<Path>>(path: P, passphrase: Secret, iterations: u32)
+                                           -> Result<EncryptedFileStore, Error> {
+        EncryptedFileStore::check_iterations(iterations)?;
+        Ok(EncryptedFileStore {
+            iterations,
+            ..EncryptedFileStore::new(path, passphrase)
+        })
+    }
+
+    /// PBKDF2 can't run zero iterations and too many would never finish
+    fn check_iterations(iterations: u32) -> Result<(), Error> {
+        if iterations == 0 || iterations > MAX_ITERATIONS {
+            return Err(Error::Storage(format!("{} key derivation iterations are out of range \
+                                               1..={}", iterations, MAX_ITERATIONS)))
+        }
+        Ok(())
+    }
+
+    /// Derive encryption key from the passphrase,
+    /// wipe it by `wipe_bytes` once it is used
+    fn derive_key(&self, salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut mac = Hmac::new(Sha256::new(), self.passphrase.expose().as_bytes());
+        let mut key = vec![0u8; 32];
+        pbkdf2(&mut mac, salt, iterations, &mut key);
+        key
+    }
+
+    /// Read and decrypt whole content of the file, missing file is an empty store
+    fn read_document(&self) -> Result<Document, Error> {
+        let mut data = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut data).map_err(|err| {
+                    Error::Storage(format!("can't read {}: {}", self.path.display(), err))
+                })?;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Document::new()),
+            Err(err) => {
+                return Err(Error::Storage(format!("can't open {}: {}", self.path.display(), err)))
+            }
+        }
+
+        if data.len() < HEADER_LEN + TAG_LEN || &data[..4] != MAGIC {
+            return Err(Error::Storage(format!("{} is not an encrypted token store",
+                                              self.path.display())))
+        }
+        if data[4] != VERSION {
+            return Err(Error::Storage(format!("unsupported token store version {}", data[4])))
+        }
+
+        let iterations = (u32::from(data[5]) << 24) | (u32::from(data[6]) << 16) |
+                         (u32::from(data[7]) << 8) | u32::from(data[8]);
+        EncryptedFileStore::check_iterations(iterations)?;
+        let salt = &data[9..9 + SALT_LEN];
+        let nonce = &data[9 + SALT_LEN..HEADER_LEN];
+        let (ciphertext, tag) = data[HEADER_LEN..].split_at(data.len() - HEADER_LEN - TAG_LEN);
+
+        let mut key = self.derive_key(salt, iterations);
+        let mut plaintext = vec![0u8; ciphertext.len()];
+        let mut cipher = ChaCha20Poly1305::new(&key, nonce, &data[..HEADER_LEN]);
+        wipe_bytes(&mut key);
+        if !cipher.decrypt(ciphertext, &mut plaintext, tag) {
+            wipe_bytes(&mut plaintext);
+            return Err(Error::Storage("token store can't be decrypted, wrong passphrase \
+                                       or the file was modified".to_string()))
+        }
+
+        let content = String::from_utf8(plaintext).map_err(|err| {
+            wipe_bytes(&mut err.into_bytes());
+            Error::Storage("decrypted token store is not valid".to_string())
+        })?;
+        Document::parse(&Secret::new(content))
+            .ok_or_else(|| Error::Storage("decrypted token store is not valid".to_string()))
+    }
+
+    /// Encrypt document with fresh salt and nonce and replace content of the file
+    fn write_document(&self, document: Document) -> Result<(), Error> {
+        let plaintext = document.to_text(false);
+        let plaintext = plaintext.expose();
+
+        let mut rng = OsRng::new()
+            .map_err(|err| Error::Storage(format!("can't get random numbers: {}", err)))?;
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let mut data = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&[(self.iterations >> 24) as u8, (self.iterations >> 16) as u8,
+                                 (self.iterations >> 8) as u8, self.iterations as u8]);
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&nonce);
+
+        let mut key = self.derive_key(&salt, self.iterations);
+        let mut ciphertext = vec![0u8; plaintext.len()];
+        let mut tag = [0u8; TAG_LEN];
+        let mut cipher = ChaCha20Poly1305::new(&key, &nonce, &data);
+        wipe_bytes(&mut key);
+        cipher.encrypt(plaintext.as_bytes(), &mut ciphertext, &mut tag);
+
+        data.extend_from_slice(&ciphertext);
+        data.extend_from_slice(&tag);
+        write_private_file(&self.path, &data)
+    }
+}
+
+impl TokenStore for EncryptedFileStore {
+    fn save(&self, service: ServiceType, account: &str, token: &StoredToken) -> Result<(), Error> {
+        let mut document = self.read_document()?;
+        save_to_document(&mut document, service, account, token)?;
+        self.write_document(document)
+    }
+
+    fn load(&self, service: ServiceType, account: &str) -> Result<Option<StoredToken>, Error> {
+        load_from_document(&self.read_document()?, service, account)
+    }
+
+    fn delete(&self, service: ServiceType, account: &str) -> Result<(), Error> {
+        let mut document = self.read_document()?;
+        if delete_from_document(&mut document, service, account) {
+            self.write_document(document)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Store keeping tokens in JSON file readable only by its owner.
+
+use super::{delete_from_document, load_from_document, save_to_document};
+use super::{write_private_file, Document, StoredToken, TokenStore};
+use super::super::{Error, Secret, ServiceType};
+
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Store keeping tokens in JSON file in form:
+///
+/// `{"deezer": {"account": {"token": "...", "expires_at": 1444000000}}}`
+///
+/// `expires_at` is in seconds since unix epoch, `null` for tokens which
+/// never expire. The file is created with `0600` permissions.
+///
+/// # Examples
+///
+/// ```
+/// use std::env;
+/// use std::fs;
+/// use music_streamer::auth::{Permission, Secret, ServiceType};
+/// use music_streamer::auth::store::{JsonFileStore, StoredToken, TokenStore};
+///
+/// let path = env::temp_dir().join("music_streamer_json_store_doc.json");
+/// let store = JsonFileStore::new(&path);
+/// let token = StoredToken {
+///     token: Secret::from("token"),
+///     expires_at: None,
+///     permissions: vec![Permission::BasicAccess, Permission::OfflineAccess],
+///     refresh_token: None,
+/// };
+///
+/// store.save(ServiceType::DEEZER, "user", &token).unwrap();
+/// assert_eq!(store.load(ServiceType::DEEZER, "user").unwrap(), Some(token));
+///
+/// # #[cfg(unix)] {
+/// use std::os::unix::fs::PermissionsExt;
+/// let mode = fs::metadata(&path).unwrap().permissions().mode();
+/// assert_eq!(mode & 0o777, 0o600);
+/// # }
+///
+/// store.delete(ServiceType::DEEZER, "user").unwrap();
+/// assert_eq!(store.load(ServiceType::DEEZER, "user").unwrap(), None);
+/// # fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+impl JsonFileStore {
+    //! Token store backed by JSON file.
+
+    /// Create store using file on given path.
+    /// File is created with first saved token.
+    pub fn new<P: AsRef<Path>>(path: P) -> JsonFileStore {
+        JsonFileStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Read whole content of the file, missing file is an empty store
+    fn read_document(&self) -> Result<Document, Error> {
+        let mut content = String::new();
+        match File::open(&self.path) {
+            Ok(mut file) => {
+                file.read_to_string(&mut content).map_err(|err| {
+                    Error::Storage(format!("can't read {}: {}", self.path.display(), err))
+                })?;
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Document::new()),
+            Err(err) => {
+                return Err(Error::Storage(format!("can't open {}: {}", self.path.display(), err)))
+            }
+        }
+
+        Document::parse(&Secret::new(content)).ok_or_else(|| {
+            Error::Storage(format!("{} is not a token store", self.path.display()))
+        })
+    }
+
+    /// Replace content of the file
+    fn write_document(&self, document: Document) -> Result<(), Error> {
+        write_private_file(&self.path, document.to_text(true).expose().as_bytes())
+    }
+}
+
+impl TokenStore for JsonFileStore {
+    fn save(&self, service: ServiceType, account: &str, token: &StoredToken) -> Result<(), Error> {
+        let mut document = self.read_document()?;
+        save_to_document(&mut document, service, account, token)?;
+        self.write_document(document)
+    }
+
+    fn load(&self, service: ServiceType, account: &str) -> Result<Option<StoredToken>, Error> {
+        load_from_document(&self.read_document()?, service, account)
+    }
+
+    fn delete(&self, service: ServiceType, account: &str) -> Result<(), Error> {
+        let mut document = self.read_document()?;
+        if delete_from_document(&mut document, service, account) {
+            self.write_document(document)?;
+        }
+        Ok(())
+    }
+}
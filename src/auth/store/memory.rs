@@ -0,0 +1,60 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Store keeping tokens in memory only.
+
+use super::{StoredToken, TokenStore};
+use super::super::{Error, ServiceType};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Store keeping tokens in memory.
+/// Clones of the store share the same tokens.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    tokens: Arc<Mutex<HashMap<(ServiceType, String), StoredToken>>>,
+}
+
+impl MemoryStore {
+    //! In-memory token store, useful for tests and short living processes.
+
+    /// Create new empty store
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl TokenStore for MemoryStore {
+    fn save(&self, service: ServiceType, account: &str, token: &StoredToken) -> Result<(), Error> {
+        let mut tokens = self.tokens.lock()
+            .map_err(|_| Error::Storage("memory store lock poisoned".to_string()))?;
+        tokens.insert((service, account.to_string()), token.clone());
+        Ok(())
+    }
+
+    fn load(&self, service: ServiceType, account: &str) -> Result<Option<StoredToken>, Error> {
+        let tokens = self.tokens.lock()
+            .map_err(|_| Error::Storage("memory store lock poisoned".to_string()))?;
+        Ok(tokens.get(&(service, account.to_string())).cloned())
+    }
+
+    fn delete(&self, service: ServiceType, account: &str) -> Result<(), Error> {
+        let mut tokens = self.tokens.lock()
+            .map_err(|_| Error::Storage("memory store lock poisoned".to_string()))?;
+        tokens.remove(&(service, account.to_string()));
+        Ok(())
+    }
+}
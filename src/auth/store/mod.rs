@@ -0,0 +1,271 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent storage of tokens so the user doesn't have to go through
+//! the authorization again after every restart.
+//!
+//! Tokens are stored by service and account. Attach a store to an
+//! authenticator with `Authenticator::attach_store`, the authenticator
+//! restores the token from it and saves every new token into it.
+
+mod encrypted;
+mod file;
+mod memory;
+
+pub use self::encrypted::EncryptedFileStore;
+pub use self::file::JsonFileStore;
+pub use self::memory::MemoryStore;
+
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceType;
+use super::wipe_json;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::json::Json;
+
+/// Token with everything needed to restore the authorization
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredToken {
+    /// Access token of the user
+    pub token: Secret,
+    /// Time when the token expires, `None` if it never expires
+    pub expires_at: Option<SystemTime>,
+    /// Permissions granted with the token
+    pub permissions: Vec<Permission>,
+    /// Token used to get new access token, `None` if the service
+    /// doesn't issue it
+    pub refresh_token: Option<Secret>,
+}
+
+impl StoredToken {
+    /// Check if the stored token didn't expire yet
+    pub fn is_valid(&self) -> bool {
+        match self.expires_at {
+            Some(expires) => SystemTime::now() < expires,
+            None => true,
+        }
+    }
+
+    /// Convert token to JSON object used by the file backends,
+    /// the object is zeroed by `Document`
+    fn to_json(&self) -> Json {
+        let mut object = BTreeMap::new();
+        object.insert("token".to_string(), Json::String(self.token.expose().to_string()));
+        object.insert("expires_at".to_string(), match self.expires_at {
+            Some(expires) => Json::U64(to_unix(expires)),
+            None => Json::Null,
+        });
+        object.insert("permissions".to_string(), Json::Array(
+            self.permissions.iter().map(|perm| Json::String(perm.as_str().to_string())).collect()
+        ));
+        if let Some(ref refresh) = self.refresh_token {
+            object.insert("refresh_token".to_string(),
+                          Json::String(refresh.expose().to_string()));
+        }
+        Json::Object(object)
+    }
+
+    /// Read token from JSON object used by the file backends
+    fn from_json(json: &Json) -> Result<StoredToken, Error> {
+        let token = json.find("token").and_then(|token| token.as_string())
+            .ok_or_else(|| Error::Storage("stored token is missing".to_string()))?;
+
+        let expires_at = match json.find("expires_at") {
+            None | Some(&Json::Null) => None,
+            Some(expires) => {
+                let seconds = expires.as_u64()
+                    .ok_or_else(|| Error::Storage("invalid stored expiry".to_string()))?;
+                Some(from_unix(seconds))
+            }
+        };
+
+        // stores written before permissions were recorded don't have them
+        let mut permissions = Vec::new();
+        if let Some(stored) = json.find("permissions") {
+            let stored = stored.as_array()
+                .ok_or_else(|| Error::Storage("invalid stored permissions".to_string()))?;
+            for perm in stored {
+                let name = perm.as_string()
+                    .ok_or_else(|| Error::Storage("invalid stored permission".to_string()))?;
+                permissions.push(name.parse().map_err(|_| {
+                    Error::Storage(format!("unknown stored permission '{}'", name))
+                })?);
+            }
+        }
+
+        let refresh_token = match json.find("refresh_token") {
+            None => None,
+            Some(refresh) => Some(Secret::from(refresh.as_string().ok_or_else(|| {
+                Error::Storage("invalid stored refresh token".to_string())
+            })?)),
+        };
+
+        Ok(StoredToken {
+            token: Secret::from(token),
+            expires_at,
+            permissions,
+            refresh_token,
+        })
+    }
+}
+
+/// Storage of tokens keyed by service and account
+pub trait TokenStore: Send {
+    /// Save token of the account, existing token is replaced
+    fn save(&self, service: ServiceType, account: &str, token: &StoredToken) -> Result<(), Error>;
+
+    /// Load token of the account, `None` if there is no token saved
+    fn load(&self, service: ServiceType, account: &str) -> Result<Option<StoredToken>, Error>;
+
+    /// Delete token of the account, deleting missing token is not an error
+    fn delete(&self, service: ServiceType, account: &str) -> Result<(), Error>;
+}
+
+/// Document shared by the file backends, tokens are grouped
+/// by service name and then by account.
+/// Values are zeroed when the document is dropped.
+struct Document(BTreeMap<String, Json>);
+
+impl Document {
+    /// Create empty document
+    fn new() -> Document {
+        Document(BTreeMap::new())
+    }
+
+    /// Read document from JSON text, `None` if the text is not an object
+    fn parse(text: &Secret) -> Option<Document> {
+        match Json::from_str(text.expose()) {
+            Ok(Json::Object(document)) => Some(Document(document)),
+            Ok(mut other) => {
+                wipe_json(&mut other);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Write document as JSON text
+    fn to_text(&self, pretty: bool) -> Secret {
+        let mut json = Json::Object(self.0.clone());
+        let text = if pretty {
+            json.pretty().to_string()
+        } else {
+            json.to_string()
+        };
+        wipe_json(&mut json);
+        Secret::new(text)
+    }
+}
+
+impl Drop for Document {
+    fn drop(&mut self) {
+        self.0.values_mut().for_each(wipe_json);
+    }
+}
+
+/// Put token of the account into the document
+fn save_to_document(document: &mut Document, service: ServiceType, account: &str,
+                    token: &StoredToken) -> Result<(), Error> {
+    let accounts = document.0.entry(service.name().to_string())
+        .or_insert_with(|| Json::Object(BTreeMap::new()));
+
+    match *accounts {
+        Json::Object(ref mut accounts) => {
+            if let Some(mut old) = accounts.insert(account.to_string(), token.to_json()) {
+                wipe_json(&mut old);
+            }
+            Ok(())
+        }
+        _ => Err(Error::Storage("invalid service entry in the store".to_string())),
+    }
+}
+
+/// Get token of the account from the document
+fn load_from_document(document: &Document, service: ServiceType, account: &str)
+                      -> Result<Option<StoredToken>, Error> {
+    match document.0.get(service.name()).and_then(|accounts| accounts.find(account)) {
+        Some(token) => Ok(Some(StoredToken::from_json(token)?)),
+        None => Ok(None),
+    }
+}
+
+/// Remove token of the account from the document.
+/// Returns true if there was anything removed.
+fn delete_from_document(document: &mut Document, service: ServiceType, account: &str) -> bool {
+    match document.0.get_mut(service.name()) {
+        Some(&mut Json::Object(ref mut accounts)) => match accounts.remove(account) {
+            Some(mut removed) => {
+                wipe_json(&mut removed);
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Seconds since unix epoch
+pub(crate) fn to_unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Time from seconds since unix epoch
+pub(crate) fn from_unix(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+/// Replace content of the file with data readable only by the owner.
+/// Data are written to temporary file first which is then renamed
+/// so the file is never left half written.
+fn write_private_file(path: &Path, data: &[u8]) -> Result<(), Error> {
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| Error::Storage(format!("invalid store path {}", path.display())))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let result = options.open(&tmp_path).and_then(|mut file| {
+        // mode is applied only to newly created files
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        file.write_all(data)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&tmp_path, path));
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(Error::Storage(format!("can't write {}: {}", path.display(), err)))
+    }
+
+    Ok(())
+}
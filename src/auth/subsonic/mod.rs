@@ -0,0 +1,475 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Subsonic implementation of authorization and authentication trait,
+//! works with every server speaking Subsonic API like Navidrome.
+//!
+//! Subsonic has no authorization links, the user logs in by name and
+//! password. Every API request carries `u`, `t` and `s` parameters where
+//! `t` is MD5 of the password followed by the salt `s`. Servers which
+//! can't check tokens (LDAP users) need the legacy password mode sending
+//! the hex encoded password as `p`.
+//!
+//! # Examples
+//!
+//! ```
+//! extern crate crypto;
+//! extern crate music_streamer;
+//! # fn main() {
+//! use crypto::digest::Digest;
+//! use crypto::md5::Md5;
+//! use music_streamer::auth::subsonic::AuthSubsonic;
+//! use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+//! use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+//! use music_streamer::http::{MockTransport, Response};
+//!
+//! let mock = MockTransport::new();
+//! mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"ok"}}"#));
+//! let config = ServiceConfig::new(ServiceType::Subsonic)
+//!     .with_api_url("http://127.0.0.1:4533/navidrome");
+//! let mut auth = AuthSubsonic::with_config(config, Box::new(mock.clone()));
+//! auth.login("alice", &Secret::from("sesame")).unwrap();
+//! assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+//!
+//! // /navidrome/rest/ping.view?u=alice&t=TOKEN&s=SALT&v=1.16.1&c=music_streamer&f=json
+//! let url = mock.requests()[0].url.clone();
+//! assert!(url.starts_with("http://127.0.0.1:4533/navidrome/rest/ping.view?u=alice&t="));
+//! assert!(url.ends_with("&v=1.16.1&c=music_streamer&f=json"));
+//! let param = |name: &str| {
+//!     url.split(|c| c == '?' || c == '&')
+//!         .find(|param| param.starts_with(name))
+//!         .map(|param| param[name.len()..].to_string())
+//!         .unwrap()
+//! };
+//! let mut md5 = Md5::new();
+//! md5.input_str(&format!("sesame{}", param("s=")));
+//! assert_eq!(param("t="), md5.result_str());
+//! assert!(!url.contains("sesame"));
+//! # }
+//! ```
+
+use super::Authenticator;
+use super::AuthorizationStatus;
+use super::CredentialAuthenticator;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceConfig;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::random::random_string;
+use super::store::TokenStore;
+use super::wipe_string;
+use super::token::TokenState;
+use super::uri::build_uri;
+use http::{HttpTransport, HyperTransport, Request};
+
+use std::time::{Duration, SystemTime};
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::Json;
+use url::form_urlencoded;
+
+/// Version of Subsonic API sent with requests, token authentication
+/// needs at least 1.13.0
+pub const API_VERSION: &str = "1.16.1";
+
+/// Number of random bytes in the salt
+const SALT_LENGTH: usize = 9;
+
+/// Way how the password proves the identity of the user
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthMode {
+    /// Salted MD5 token `t` with salt `s`, password never leaves the client.
+    /// The salt is chosen once per login and the same `t` and `s` go with
+    /// every request until the next login, so anyone who sees them can
+    /// replay them like a password. Log in again to get a new salt.
+    Token,
+    /// Legacy hex encoded password `p`, for servers without token support.
+    /// `p` is the password itself, it is kept only in memory: it is never
+    /// written to a store nor exported in a snapshot.
+    Password,
+}
+
+/// Store information about authorization progress and credentials
+pub struct AuthSubsonic {
+    tokens: TokenState,
+    transport: Box<dyn HttpTransport>,
+    config: ServiceConfig,
+    mode: AuthMode,
+    client: String,
+}
+
+impl AuthSubsonic {
+    //! Authentication object for Subsonic servers.
+
+    /// Create new Subsonic authentication object for the server
+    /// at `http://localhost:4533`
+    pub fn new() -> AuthSubsonic {
+        AuthSubsonic::with_transport(Box::new(HyperTransport::new()))
+    }
+
+    /// Create new Subsonic authentication object which sends
+    /// all requests by given transport
+    pub fn with_transport(transport: Box<dyn HttpTransport>) -> AuthSubsonic {
+        AuthSubsonic::with_config(AuthSubsonic::default_config(), transport)
+    }
+
+    /// Create new Subsonic authentication object for the server
+    /// at `config.api_url` using given transport
+    pub fn with_config(config: ServiceConfig, transport: Box<dyn HttpTransport>) -> AuthSubsonic {
+        AuthSubsonic {
+            tokens: TokenState::permanent(ServiceType::Subsonic)
+                .with_memory_only(AuthSubsonic::carries_password)
+                .with_verification(),
+            transport,
+            config,
+            mode: AuthMode::Token,
+            client: "music_streamer".to_string(),
+        }
+    }
+
+    /// Subsonic servers are self-hosted, default is local Navidrome.
+    /// Only `api_url` is used.
+    pub fn default_config() -> ServiceConfig {
+        ServiceConfig {
+            auth_url: String::new(),
+            token_url: String::new(),
+            api_url: "http://localhost:4533".to_string(),
+            revoke_url: None,
+        }
+    }
+
+    /// Select how the password is sent, `AuthMode::Token` is default
+    pub fn set_mode(&mut self, mode: AuthMode) {
+        self.mode = mode;
+    }
+
+    /// Get how the password is sent
+    pub fn mode(&self) -> AuthMode {
+        self.mode
+    }
+
+    /// Set client name sent as `c` parameter, default is `music_streamer`
+    pub fn set_client(&mut self, client: &str) {
+        self.client = client.to_string();
+    }
+
+    /// Check the current token by `ping.view`, used after the token
+    /// is restored from a store. Accepted token completes the authorization,
+    /// refused token moves the status to `Failed`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::subsonic::AuthSubsonic;
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"ok"}}"#));
+    ///
+    /// let mut auth = AuthSubsonic::with_transport(Box::new(mock.clone()));
+    /// auth.save_token(Secret::from("u=alice&t=26719a1196d2a940705a59634eb18eab&s=c19b2d"))
+    ///     .unwrap();
+    /// assert_eq!(auth.status(), AuthorizationStatus::TokenAcquired);
+    ///
+    /// auth.ping().unwrap();
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert!(mock.requests()[0].url.contains("?u=alice&t=26719a1196d2a940705a59634eb18eab\
+    ///                                          &s=c19b2d&v=1.16.1"));
+    /// ```
+    pub fn ping(&mut self) -> Result<(), Error> {
+        match self.check(self.get_token()?) {
+            Ok(()) => self.tokens.transition(AuthorizationStatus::AuthorizationCompleted),
+            Err(Error::OAuth(reason)) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(Error::OAuth(reason))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Send `ping.view` with the token, the server refuses wrong
+    /// credentials by `failed` status with an error message
+    fn check(&self, token: &Secret) -> Result<(), Error> {
+        let ping = format!("{}/rest/ping.view?{}", self.config.api_url.trim_end_matches('/'),
+                           token.expose());
+        let uri = Secret::new(build_uri(&ping, &[
+            ("v", API_VERSION),
+            ("c", &self.client),
+            ("f", "json"),
+        ])?);
+
+        let res = self.transport.send(&Request::get(uri.expose()))?;
+        debug!("subsonic ping answered with status {}", res.status);
+        if !res.is_success() {
+            return Err(Error::HttpStatus(res.status))
+        }
+
+        let json = Json::from_str(&res.text())
+            .map_err(|err| Error::Parse(format!("invalid ping response: {}", err)))?;
+        let response = json.find("subsonic-response")
+            .ok_or_else(|| Error::Parse("not a subsonic response".to_string()))?;
+        match response.find("status").and_then(|status| status.as_string()) {
+            Some("ok") => Ok(()),
+            Some("failed") => {
+                let error = response.find("error");
+                let reason = error.and_then(|error| error.find("message"))
+                    .and_then(|message| message.as_string())
+                    .map(str::to_string)
+                    .or_else(|| {
+                        error.and_then(|error| error.find("code"))
+                            .map(|code| format!("error {}", code))
+                    })
+                    .unwrap_or_else(|| "unknown error".to_string());
+                Err(Error::OAuth(reason))
+            }
+            _ => Err(Error::Parse("invalid ping response status".to_string())),
+        }
+    }
+
+    /// Check if the token carries the password itself (`p=...`)
+    fn carries_password(token: &Secret) -> bool {
+        token.expose().split('&').any(|param| param.starts_with("p="))
+    }
+}
+
+impl Default for AuthSubsonic {
+    fn default() -> AuthSubsonic {
+        AuthSubsonic::new()
+    }
+}
+
+impl Authenticator for AuthSubsonic {
+
+    fn status(&self) -> AuthorizationStatus {
+        self.tokens.status()
+    }
+
+    fn on_status_change(&mut self, observer: StatusObserver) {
+        self.tokens.subscribe(observer);
+    }
+
+    /// Get server used by the authenticator
+    fn config(&self) -> &ServiceConfig {
+        &self.config
+    }
+
+    /// Subsonic has no authorization page, use `login`
+    fn get_authorize_link(&mut self, _app_id: &str, _redirect_uri: &str,
+                          _permissions: &[Permission]) -> Result<String, Error> {
+        Err(Error::InvalidState("subsonic logs in by user name and password"))
+    }
+
+    /// Subsonic has no authorization page, use `login`
+    fn parse_response_code(&mut self, _response: &str) -> Result<Secret, Error> {
+        Err(Error::InvalidState("subsonic logs in by user name and password"))
+    }
+
+    /// Subsonic has no authorization codes, use `login`
+    fn authenticate_application(&mut self, _app_id: &str, _app_secret: &Secret,
+                                _code: &Secret) -> Result<(), Error> {
+        Err(Error::InvalidState("subsonic logs in by user name and password"))
+    }
+
+    /// Save token in form of `u=...&t=...&s=...` or `u=...&p=...`.
+    /// Token with the password (`p`) is kept only in memory.
+    fn save_token(&mut self, token: Secret) -> Result<(), Error> {
+        self.save_token_with_expiry(token, None)
+    }
+
+    /// Save token, Subsonic tokens never expire
+    fn save_token_with_expiry(&mut self, token: Secret, expires_at: Option<SystemTime>)
+                              -> Result<(), Error> {
+        self.tokens.save(token, expires_at)
+    }
+
+    /// Get authentication parameters appended to every API request
+    ///
+    /// DO NOT STORE THE TOKEN ELSEWHERE
+    fn get_token(&self) -> Result<&Secret, Error> {
+        self.tokens.get().map_err(|_| Error::InvalidState("user is not logged in"))
+    }
+
+    fn is_token_valid(&self) -> bool {
+        self.tokens.is_valid()
+    }
+
+    /// Subsonic tokens never expire
+    fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Subsonic tokens never expire
+    fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        self.get_token().map(|_| None)
+    }
+
+    /// Subsonic has no permissions, access is given by user roles on the server
+    fn granted_permissions(&self) -> &[Permission] {
+        self.tokens.granted()
+    }
+
+    /// Attach store and restore token of the account from it.
+    /// Restored token stays `TokenAcquired` until `verify` checks it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::subsonic::{AuthMode, AuthSubsonic};
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::{Secret, ServiceType};
+    /// use music_streamer::auth::store::{MemoryStore, TokenStore};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let store = MemoryStore::new();
+    /// let mock = MockTransport::new();
+    /// for _ in 0..3 {
+    ///     mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"ok"}}"#));
+    /// }
+    ///
+    /// let mut auth = AuthSubsonic::with_transport(Box::new(mock.clone()));
+    /// auth.attach_store(Box::new(store.clone()), "alice").unwrap();
+    /// auth.login("alice", &Secret::from("sesame")).unwrap();
+    /// let saved = store.load(ServiceType::Subsonic, "alice").unwrap().unwrap();
+    /// assert_eq!(&saved.token, auth.get_token().unwrap());
+    ///
+    /// let mut restored = AuthSubsonic::with_transport(Box::new(mock.clone()));
+    /// assert!(restored.attach_store(Box::new(store.clone()), "alice").unwrap());
+    /// assert_eq!(restored.status(), AuthorizationStatus::TokenAcquired);
+    /// restored.verify().unwrap();
+    /// assert_eq!(restored.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// assert!(mock.requests()[1].url.contains("/rest/ping.view?u=alice&t="));
+    ///
+    /// // the password itself never reaches the store nor a snapshot
+    /// auth.set_mode(AuthMode::Password);
+    /// auth.login("alice", &Secret::from("sesame")).unwrap();
+    /// assert!(store.load(ServiceType::Subsonic, "alice").unwrap().is_none());
+    /// assert!(auth.export_snapshot(true).token.is_none());
+    /// assert!(!AuthSubsonic::new().attach_store(Box::new(store), "alice").unwrap());
+    /// ```
+    fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str) -> Result<bool, Error> {
+        self.tokens.attach_store(store, account)
+    }
+
+    fn logout(&mut self) -> Result<(), Error> {
+        self.tokens.logout()
+    }
+
+    /// Subsonic has no sessions on the server, the token is only forgotten
+    fn revoke(&mut self) -> Result<(), Error> {
+        if !self.tokens.has_token() {
+            return self.logout()
+        }
+
+        self.tokens.revoke(Ok(()))
+    }
+
+    /// Export the session, token with the password is never exported
+    fn export_snapshot(&self, include_secrets: bool) -> SessionSnapshot {
+        self.tokens.export(include_secrets)
+    }
+
+    /// Restore the session from the snapshot, the token is saved
+    /// to the attached store. It stays `TokenAcquired` until `verify`
+    /// checks it.
+    fn import_snapshot(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        self.tokens.import(snapshot)
+    }
+
+    fn as_credentials(&mut self) -> Option<&mut dyn CredentialAuthenticator> {
+        Some(self)
+    }
+}
+
+impl CredentialAuthenticator for AuthSubsonic {
+    /// Log the user in. Credentials are checked by `ping.view` and kept
+    /// as the token (`u=...&t=...&s=...` or `u=...&p=enc:...`) which is
+    /// appended to every API request. In `AuthMode::Token` only the salted
+    /// hash is kept, in `AuthMode::Password` the token is the reversibly
+    /// encoded password and it stays only in memory, see `AuthMode`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use music_streamer::auth::subsonic::{AuthMode, AuthSubsonic};
+    /// use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+    /// use music_streamer::auth::{Error, Secret};
+    /// use music_streamer::http::{MockTransport, Response};
+    ///
+    /// let mock = MockTransport::new();
+    /// mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"failed",
+    ///     "version":"1.16.1","error":{"code":41,
+    ///     "message":"Token authentication not supported for LDAP users."}}}"#));
+    /// mock.push_response(Response::new(200, r#"{"subsonic-response":{"status":"ok"}}"#));
+    ///
+    /// let mut auth = AuthSubsonic::with_transport(Box::new(mock.clone()));
+    /// match auth.login("alice", &Secret::from("sesame")) {
+    ///     Err(Error::OAuth(reason)) => assert!(reason.starts_with("Token authentication")),
+    ///     other => panic!("unexpected {:?}", other),
+    /// }
+    /// assert_eq!(auth.status(), AuthorizationStatus::Failed);
+    ///
+    /// auth.set_mode(AuthMode::Password);
+    /// auth.login("alice", &Secret::from("sesame")).unwrap();
+    /// assert_eq!(auth.get_token().unwrap().expose(), "u=alice&p=enc%3A736573616d65");
+    /// assert_eq!(mock.requests()[1].url, "http://localhost:4533/rest/ping.view\
+    ///                                     ?u=alice&p=enc%3A736573616d65\
+    ///                                     &v=1.16.1&c=music_streamer&f=json");
+    /// assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    /// ```
+    fn login(&mut self, username: &str, password: &Secret) -> Result<(), Error> {
+        if username.is_empty() || password.is_empty() {
+            return Err(Error::InvalidState("user name and password are required"))
+        }
+        self.tokens.transition(AuthorizationStatus::UserAuthentication)?;
+
+        let token = Secret::new(match self.mode {
+            AuthMode::Token => {
+                let salt = random_string(SALT_LENGTH)?;
+                let mut md5 = Md5::new();
+                md5.input_str(password.expose());
+                md5.input_str(&salt);
+                let hash = Secret::new(md5.result_str());
+                form_urlencoded::serialize(&[("u", username), ("t", hash.expose()), ("s", &salt)])
+            }
+            AuthMode::Password => {
+                let mut hex = password.expose().as_bytes().to_hex();
+                let encoded = Secret::new(format!("enc:{}", hex));
+                wipe_string(&mut hex);
+                form_urlencoded::serialize(&[("u", username), ("p", encoded.expose())])
+            }
+        });
+
+        match self.check(&token) {
+            Ok(()) => {
+                self.save_token(token)?;
+                self.tokens.transition(AuthorizationStatus::AuthorizationCompleted)
+            }
+            Err(err) => {
+                self.tokens.transition(AuthorizationStatus::Failed)?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Check the current token by `ping.view`
+    fn verify(&mut self) -> Result<(), Error> {
+        self.ping()
+    }
+}
@@ -0,0 +1,374 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Token of an authenticator together with everything what goes with it,
+//! shared by all authenticators so they only add what their service needs.
+
+use super::AuthorizationStatus;
+use super::Error;
+use super::Permission;
+use super::Secret;
+use super::ServiceType;
+use super::SessionSnapshot;
+use super::StatusObserver;
+use super::snapshot::SNAPSHOT_VERSION;
+use super::state::StatusMachine;
+use super::store::{StoredToken, TokenStore};
+use http::Response;
+
+use std::io;
+use std::time::{Duration, SystemTime};
+
+/// Status of the authorization, the token with its expiry, granted
+/// permissions and refresh token, and the store the token is saved to.
+/// Authenticators forward the token part of `Authenticator` to it.
+pub struct TokenState {
+    service: ServiceType,
+    expiring: bool,
+    memory_only: Option<fn(&Secret) -> bool>,
+    verify_restored: bool,
+    persistent: bool,
+    status: StatusMachine,
+    token: Secret,
+    expires: Option<SystemTime>,
+    granted: Vec<Permission>,
+    refresh: Option<Secret>,
+    store: Option<(Box<dyn TokenStore>, String)>,
+}
+
+impl TokenState {
+    //! Token shared by the authenticators.
+
+    /// Create empty state for tokens of the service which can expire
+    pub fn new(service: ServiceType) -> TokenState {
+        TokenState {
+            service,
+            expiring: true,
+            memory_only: None,
+            verify_restored: false,
+            persistent: true,
+            status: StatusMachine::new(),
+            token: Secret::default(),
+            expires: None,
+            granted: Vec::new(),
+            refresh: None,
+            store: None,
+        }
+    }
+
+    /// Create empty state for tokens of the service which never expire,
+    /// tokens with expiry are refused
+    pub fn permanent(service: ServiceType) -> TokenState {
+        TokenState {
+            expiring: false,
+            ..TokenState::new(service)
+        }
+    }
+
+    /// Keep tokens matched by `memory_only` only in memory. Such tokens
+    /// are never written to the store, a token saved there earlier is
+    /// deleted, and snapshots leave them out.
+    pub fn with_memory_only(mut self, memory_only: fn(&Secret) -> bool) -> TokenState {
+        self.memory_only = Some(memory_only);
+        self
+    }
+
+    /// Stop restored tokens at `TokenAcquired`, the authenticator
+    /// completes the authorization once the service accepted them
+    pub fn with_verification(mut self) -> TokenState {
+        self.verify_restored = true;
+        self
+    }
+
+    /// Get status of the authorization.
+    /// Once the token runs out the status is `Expired`, observers learn
+    /// about the expiry when the status is read.
+    pub fn status(&self) -> AuthorizationStatus {
+        match self.status.get() {
+            AuthorizationStatus::TokenAcquired |
+            AuthorizationStatus::AuthorizationCompleted if !self.is_valid() => {
+                // both statuses are allowed to expire
+                let _ = self.status.transition(AuthorizationStatus::Expired);
+                AuthorizationStatus::Expired
+            }
+            status => status,
+        }
+    }
+
+    /// Move the authorization to another status
+    pub fn transition(&self, to: AuthorizationStatus) -> Result<(), Error> {
+        self.status.transition(to)
+    }
+
+    /// Register callback which receives every status change
+    pub fn subscribe(&self, observer: StatusObserver) {
+        self.status.subscribe(observer);
+    }
+
+    /// Save token to the attached store and keep it, the status
+    /// moves to `TokenAcquired`. Granted permissions and refresh
+    /// token are saved with it.
+    pub fn save(&mut self, token: Secret, expires_at: Option<SystemTime>) -> Result<(), Error> {
+        let status = self.status();
+        if !StatusMachine::is_allowed(status, AuthorizationStatus::TokenAcquired) {
+            return Err(Error::InvalidTransition(status, AuthorizationStatus::TokenAcquired))
+        }
+
+        self.keep(token, expires_at)?;
+        self.status.transition(AuthorizationStatus::TokenAcquired)
+    }
+
+    /// Replace token of the completed authorization, like `save`
+    /// but the status stays `AuthorizationCompleted`
+    pub fn renew(&mut self, token: Secret, expires_at: Option<SystemTime>) -> Result<(), Error> {
+        if self.status() != AuthorizationStatus::AuthorizationCompleted {
+            return Err(Error::InvalidState("authorization is not completed"))
+        }
+
+        self.keep(token, expires_at)
+    }
+
+    /// Save token to the attached store and keep it
+    fn keep(&mut self, token: Secret, expires_at: Option<SystemTime>) -> Result<(), Error> {
+        let stored = StoredToken {
+            token,
+            expires_at,
+            permissions: self.granted.clone(),
+            refresh_token: self.refresh.clone(),
+        };
+        self.persistent = self.write(&stored)?;
+        self.token = stored.token;
+        self.expires = stored.expires_at;
+        Ok(())
+    }
+
+    /// Write token to the attached store, nothing is kept yet.
+    /// Returns false when the token is kept only in memory.
+    fn write(&self, stored: &StoredToken) -> Result<bool, Error> {
+        if stored.token.is_empty() {
+            return Err(Error::InvalidState("can't save empty token"))
+        }
+        if !self.expiring && stored.expires_at.is_some() {
+            return Err(Error::InvalidState("tokens of the service never expire"))
+        }
+
+        let persistent = !self.memory_only.is_some_and(|memory_only| memory_only(&stored.token));
+        if let Some((ref store, ref account)) = self.store {
+            if persistent {
+                store.save(self.service, account, stored)?;
+            } else {
+                // don't let an older token come back after restart
+                store.delete(self.service, account)?;
+            }
+        }
+        Ok(persistent)
+    }
+
+    /// Set permissions granted with the next saved token
+    pub fn set_granted(&mut self, granted: Vec<Permission>) {
+        self.granted = granted;
+    }
+
+    /// Set refresh token saved with the next token
+    pub fn set_refresh_token(&mut self, refresh: Option<Secret>) {
+        self.refresh = refresh;
+    }
+
+    /// Get refresh token, `None` if the service didn't issue it
+    pub fn refresh_token(&self) -> Option<&Secret> {
+        self.refresh.as_ref()
+    }
+
+    /// Get the token
+    pub fn get(&self) -> Result<&Secret, Error> {
+        if self.token.is_empty() {
+            return Err(Error::InvalidState("no token was acquired yet"))
+        }
+
+        Ok(&self.token)
+    }
+
+    /// Check if there is a token, valid or not
+    pub fn has_token(&self) -> bool {
+        !self.token.is_empty()
+    }
+
+    /// Check if there is a token which didn't expire yet
+    pub fn is_valid(&self) -> bool {
+        if self.token.is_empty() {
+            return false
+        }
+
+        match self.expires {
+            Some(expires) => SystemTime::now() < expires,
+            None => true,
+        }
+    }
+
+    /// Get time when the token expires, `None` if it never expires
+    pub fn expires_at(&self) -> Result<Option<SystemTime>, Error> {
+        self.get()?;
+        Ok(self.expires)
+    }
+
+    /// Get time left before the token expires, zero once it expired
+    pub fn time_remaining(&self) -> Result<Option<Duration>, Error> {
+        Ok(self.expires_at()?.map(|expires| {
+            expires.duration_since(SystemTime::now()).unwrap_or_else(|_| Duration::from_secs(0))
+        }))
+    }
+
+    /// Get permissions granted with the token
+    pub fn granted(&self) -> &[Permission] {
+        &self.granted
+    }
+
+    /// Attach store and restore the token of the account from it.
+    /// Returns true when a valid token was restored. Expired tokens
+    /// are restored only with a refresh token, the status is `Expired`
+    /// then and false is returned. Tokens which have to be verified
+    /// stay `TokenAcquired`.
+    pub fn attach_store(&mut self, store: Box<dyn TokenStore>, account: &str)
+                        -> Result<bool, Error> {
+        let stored = store.load(self.service, account)?;
+        self.store = Some((store, account.to_string()));
+
+        let stored = match stored {
+            Some(stored) => stored,
+            None => return Ok(false),
+        };
+        if stored.token.is_empty() || (!stored.is_valid() && stored.refresh_token.is_none()) {
+            return Ok(false)
+        }
+
+        let valid = stored.is_valid();
+        let status = if !valid {
+            AuthorizationStatus::Expired
+        } else if self.verify_restored {
+            AuthorizationStatus::TokenAcquired
+        } else {
+            AuthorizationStatus::AuthorizationCompleted
+        };
+        self.restore(stored, true, status)?;
+        Ok(valid)
+    }
+
+    /// Take over token saved earlier, the authorization starts over
+    /// from `Nothing` and ends in `status`
+    fn restore(&mut self, stored: StoredToken, persistent: bool, status: AuthorizationStatus)
+               -> Result<(), Error> {
+        self.status.transition(AuthorizationStatus::Nothing)?;
+        self.token = stored.token;
+        self.expires = stored.expires_at;
+        self.granted = stored.permissions;
+        self.refresh = stored.refresh_token;
+        self.persistent = persistent;
+        self.status.transition(AuthorizationStatus::TokenAcquired)?;
+        self.status.transition(status)
+    }
+
+    /// Wipe the tokens and delete them from the attached store
+    pub fn forget(&mut self) -> Result<(), Error> {
+        if let Some((ref store, ref account)) = self.store {
+            store.delete(self.service, account)?;
+        }
+
+        self.token = Secret::default();
+        self.expires = None;
+        self.granted.clear();
+        self.refresh = None;
+        Ok(())
+    }
+
+    /// Forget the tokens, the status moves to `Nothing`
+    pub fn logout(&mut self) -> Result<(), Error> {
+        self.forget()?;
+        self.status.transition(AuthorizationStatus::Nothing)
+    }
+
+    /// Forget the tokens after the service was asked to end the session,
+    /// the status moves to `Revoked`. Answer of the service is returned
+    /// afterwards so the token is forgotten even when the service fails.
+    pub fn revoke(&mut self, answer: Result<(), Error>) -> Result<(), Error> {
+        let status = self.status();
+        self.forget()?;
+        if StatusMachine::is_allowed(status, AuthorizationStatus::Revoked) {
+            self.status.transition(AuthorizationStatus::Revoked)?;
+        } else {
+            // new authorization was started while holding the token
+            self.status.transition(AuthorizationStatus::Nothing)?;
+        }
+
+        answer
+    }
+
+    /// Export the session, the tokens only when `include_secrets` is true
+    /// and they aren't kept only in memory
+    pub fn export(&self, include_secrets: bool) -> SessionSnapshot {
+        let include_secrets = include_secrets && self.persistent;
+        SessionSnapshot {
+            version: SNAPSHOT_VERSION,
+            service: self.service,
+            status: self.status(),
+            permissions: self.granted.clone(),
+            expires_at: self.expires,
+            token: if include_secrets && !self.token.is_empty() {
+                Some(self.token.clone())
+            } else {
+                None
+            },
+            refresh_token: if include_secrets { self.refresh.clone() } else { None },
+        }
+    }
+
+    /// Restore the session from the snapshot, the token is saved
+    /// to the attached store. The status starts over from `Nothing`
+    /// and ends `AuthorizationCompleted` when the snapshot was completed
+    /// or expired, `TokenAcquired` otherwise or when the token has to be
+    /// verified. Snapshot without token
+    /// changes nothing, neither does snapshot which can't be saved.
+    pub fn import(&mut self, snapshot: &SessionSnapshot) -> Result<(), Error> {
+        snapshot.check(self.service)?;
+        let token = match snapshot.token {
+            Some(ref token) if !token.is_empty() => token.clone(),
+            _ => return Ok(()),
+        };
+
+        let stored = StoredToken {
+            token,
+            expires_at: snapshot.expires_at,
+            permissions: snapshot.permissions.clone(),
+            refresh_token: snapshot.refresh_token.clone(),
+        };
+        let persistent = self.write(&stored)?;
+        let status = match snapshot.status {
+            AuthorizationStatus::AuthorizationCompleted |
+            AuthorizationStatus::Expired if !self.verify_restored => {
+                AuthorizationStatus::AuthorizationCompleted
+            }
+            _ => AuthorizationStatus::TokenAcquired,
+        };
+        self.restore(stored, persistent, status)
+    }
+}
+
+/// Answer of the service to the revoke request, only 2xx is a success
+pub fn revoke_answer(res: io::Result<Response>) -> Result<(), Error> {
+    match res {
+        Ok(ref res) if res.is_success() => Ok(()),
+        Ok(res) => Err(Error::HttpStatus(res.status)),
+        Err(err) => Err(Error::from(err)),
+    }
+}
@@ -0,0 +1,39 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Building of uris sent to the services.
+
+use super::Error;
+
+use url::{form_urlencoded, Url};
+
+/// Append query parameters to the base uri.
+/// Every name and value is percent-encoded, query already present
+/// in the base uri is kept.
+pub fn build_uri(base: &str, params: &[(&str, &str)]) -> Result<String, Error> {
+    let mut url = Url::parse(base)
+        .map_err(|err| Error::Parse(format!("invalid service uri '{}': {}", base, err)))?;
+
+    let mut pairs = url.query_pairs().unwrap_or_default();
+    pairs.extend(params.iter().map(|&(name, value)| (name.to_string(), value.to_string())));
+    url.set_query_from_pairs(pairs.iter());
+
+    Ok(url.serialize())
+}
+
+/// Encode parameters as `application/x-www-form-urlencoded` body
+pub fn build_form(params: &[(&str, &str)]) -> Vec<u8> {
+    form_urlencoded::serialize(params.iter().cloned()).into_bytes()
+}
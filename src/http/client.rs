@@ -0,0 +1,86 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Default transport using hyper client.
+
+use super::{HttpTransport, Method, Request, Response};
+
+use std::io;
+use std::io::Read;
+
+use hyper;
+use hyper::header::Headers;
+
+/// Transport sending requests by hyper client
+pub struct HyperTransport {
+    client: hyper::Client,
+}
+
+impl HyperTransport {
+    //! Hyper based transport.
+
+    /// Create transport with default hyper client
+    pub fn new() -> HyperTransport {
+        HyperTransport::with_client(hyper::Client::new())
+    }
+
+    /// Create transport using already configured client,
+    /// for example with a proxy connector or redirect policy
+    pub fn with_client(client: hyper::Client) -> HyperTransport {
+        HyperTransport {
+            client,
+        }
+    }
+}
+
+impl Default for HyperTransport {
+    fn default() -> HyperTransport {
+        HyperTransport::new()
+    }
+}
+
+impl HttpTransport for HyperTransport {
+    fn send(&self, request: &Request) -> io::Result<Response> {
+        let method = match request.method {
+            Method::Get => hyper::method::Method::Get,
+            Method::Post => hyper::method::Method::Post,
+            Method::Put => hyper::method::Method::Put,
+            Method::Delete => hyper::method::Method::Delete,
+        };
+
+        let mut headers = Headers::new();
+        for (name, value) in &request.headers {
+            headers.set_raw(name.clone(), vec![value.as_bytes().to_vec()]);
+        }
+
+        let mut builder = self.client.request(method, &request.url[..]).headers(headers);
+        if !request.body.is_empty() {
+            builder = builder.body(&request.body[..]);
+        }
+
+        let mut res = builder.send().map_err(io::Error::other)?;
+
+        let mut body = Vec::new();
+        res.read_to_end(&mut body)?;
+
+        Ok(Response {
+            status: res.status.to_u16(),
+            headers: res.headers.iter()
+                .map(|header| (header.name().to_string(), header.value_string()))
+                .collect(),
+            body,
+        })
+    }
+}
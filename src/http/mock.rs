@@ -0,0 +1,91 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Scripted transport for tests.
+
+use super::{HttpTransport, Request, Response};
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Transport answering requests with scripted responses in order.
+/// Clones of the transport share the script and the sent requests so
+/// the test can keep one clone while the other is used by authenticator.
+/// Sent requests are kept with their secrets until the last clone
+/// is dropped, the mock is meant for tests only.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::http::{HttpTransport, MockTransport, Request, Response};
+///
+/// let mock = MockTransport::new();
+/// mock.push_response(Response::new(200, "pong"));
+///
+/// let response = mock.send(&Request::get("http://localhost/ping")).unwrap();
+/// assert_eq!(response.text(), "pong");
+/// assert_eq!(mock.requests()[0].url, "http://localhost/ping");
+///
+/// // nothing scripted anymore
+/// assert!(mock.send(&Request::get("http://localhost/ping")).is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: VecDeque<Response>,
+    requests: Vec<Request>,
+}
+
+impl MockTransport {
+    //! In-memory scripted transport.
+
+    /// Create transport without any scripted response
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Add response which will be returned after all previously added ones
+    pub fn push_response(&self, response: Response) {
+        self.lock().responses.push_back(response);
+    }
+
+    /// Get all requests sent so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.lock().requests.clone()
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, MockState> {
+        // a panicking test can't leave the state inconsistent
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl HttpTransport for MockTransport {
+    fn send(&self, request: &Request) -> io::Result<Response> {
+        let mut state = self.lock();
+        state.requests.push(request.clone());
+        state.responses.pop_front().ok_or_else(|| {
+            io::Error::other("no scripted response left")
+        })
+    }
+}
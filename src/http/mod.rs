@@ -0,0 +1,192 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP transport used for all network communication of the library.
+//!
+//! Authenticators and API clients get an `HttpTransport` so proxies,
+//! timeouts or a completely different HTTP stack can be plugged in,
+//! and tests can run without the real services.
+
+mod client;
+mod mock;
+
+pub use self::client::HyperTransport;
+pub use self::mock::MockTransport;
+
+use std::fmt;
+use std::io;
+
+use auth::{wipe_bytes, wipe_string};
+
+/// HTTP method of the request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+/// Request sent by the transport.
+/// Url, headers and body can contain secrets so the request is never
+/// printed and they are zeroed when the request is dropped.
+#[derive(Clone)]
+pub struct Request {
+    /// HTTP method
+    pub method: Method,
+    /// Complete url including the query
+    pub url: String,
+    /// Additional headers as (name, value) pairs
+    pub headers: Vec<(String, String)>,
+    /// Request body, empty if there is none
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    //! Request builder.
+
+    /// Create request without headers and body
+    pub fn new(method: Method, url: &str) -> Request {
+        Request {
+            method,
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Create `GET` request
+    pub fn get(url: &str) -> Request {
+        Request::new(Method::Get, url)
+    }
+
+    /// Create `POST` request
+    pub fn post(url: &str) -> Request {
+        Request::new(Method::Post, url)
+    }
+
+    /// Add header to the request
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set body of the request
+    pub fn body(mut self, body: Vec<u8>) -> Request {
+        self.body = body;
+        self
+    }
+}
+
+/// Response received by the transport.
+/// Headers and body are zeroed when the response is dropped,
+/// token endpoints answer with the token. Debug output shows
+/// only the status and names of the headers.
+///
+/// # Examples
+///
+/// ```
+/// use music_streamer::http::Response;
+///
+/// let res = Response::new(200, r#"{"access_token":"token"}"#)
+///     .with_header("Set-Cookie", "sid=secret");
+/// assert_eq!(res.text(), r#"{"access_token":"token"}"#);
+/// // secrets in the body and headers are never printed
+/// assert_eq!(format!("{:?}", res),
+///            r#"Response { status: 200, headers: ["Set-Cookie"], body: <24 bytes> }"#);
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct Response {
+    /// HTTP status code
+    pub status: u16,
+    /// Response headers as (name, value) pairs
+    pub headers: Vec<(String, String)>,
+    /// Response body
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    //! Response of the server.
+
+    /// Create response with given status and body
+    pub fn new(status: u16, body: &str) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    /// Add header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Check if the status is 2xx
+    pub fn is_success(&self) -> bool {
+        self.status >= 200 && self.status < 300
+    }
+
+    /// Get value of the header, names are compared case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|header| header.0.eq_ignore_ascii_case(name))
+            .map(|header| &header.1[..])
+    }
+
+    /// Get body as text, invalid UTF-8 is replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.headers.iter().map(|header| &header.0[..]).collect();
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &names)
+            .field("body", &format_args!("<{} bytes>", self.body.len()))
+            .finish()
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        wipe_string(&mut self.url);
+        for header in &mut self.headers {
+            wipe_string(&mut header.1);
+        }
+        wipe_bytes(&mut self.body);
+    }
+}
+
+impl Drop for Response {
+    fn drop(&mut self) {
+        for header in &mut self.headers {
+            wipe_string(&mut header.1);
+        }
+        wipe_bytes(&mut self.body);
+    }
+}
+
+/// Something what can send HTTP requests
+pub trait HttpTransport: Send {
+    /// Send the request and wait for the whole response.
+    /// Only failures of the transport itself are errors,
+    /// any HTTP status is returned as a response.
+    fn send(&self, request: &Request) -> io::Result<Response>;
+}
@@ -13,6 +13,16 @@
 // You should have received a copy of the GNU General Public License
 // along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
 
+extern crate crypto;
 extern crate hyper;
+#[macro_use]
+extern crate log;
+extern crate rand;
+extern crate rustc_serialize;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+extern crate url;
 
 pub mod auth;
+pub mod http;
@@ -0,0 +1,83 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Local stand-in for the services, answers requests sent by the real
+//! HTTP transport and gives back what it received.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
+
+/// Server on `127.0.0.1` answering one connection per scripted body
+pub struct StandIn {
+    port: u16,
+    server: JoinHandle<Vec<String>>,
+}
+
+impl StandIn {
+    /// Start server which answers the connections in order by `200 OK`
+    /// with the JSON bodies and stops after the last one
+    pub fn start(bodies: Vec<&'static str>) -> StandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = read_request(&mut stream);
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                                Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       body.len(), body).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        StandIn { port, server }
+    }
+
+    /// Uri of the path on the server
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+
+    /// Wait until all bodies were sent and return the received requests
+    pub fn requests(self) -> Vec<String> {
+        self.server.join().unwrap()
+    }
+}
+
+/// Read the request head and the body announced by `Content-Length`
+fn read_request<R: Read>(stream: &mut R) -> String {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = stream.read(&mut buf).unwrap();
+        request.extend_from_slice(&buf[..len]);
+        let text = String::from_utf8_lossy(&request).into_owned();
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end].lines()
+                .find(|line| line.to_lowercase().starts_with("content-length:"))
+                .map(|line| line[15..].trim().parse::<usize>().unwrap())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return text
+            }
+        }
+        if len == 0 {
+            return text
+        }
+    }
+}
@@ -0,0 +1,66 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate music_streamer;
+
+mod common;
+
+use common::StandIn;
+use music_streamer::auth::jellyfin::AuthJellyfin;
+use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+use music_streamer::http::HyperTransport;
+
+fn authenticator(server: &StandIn) -> AuthJellyfin {
+    let config = ServiceConfig::new(ServiceType::Jellyfin)
+        .with_api_url(&server.url("/jellyfin/"));
+    let mut auth = AuthJellyfin::with_config(config, Box::new(HyperTransport::new())).unwrap();
+    auth.set_device("Living room", "device-1");
+    auth
+}
+
+#[test]
+fn login_sends_device_and_credentials() {
+    let server = StandIn::start(vec![
+        r#"{"User":{"Id":"4f2a","Name":"alice"},"AccessToken":"token","ServerId":"s"}"#,
+    ]);
+    let mut auth = authenticator(&server);
+    auth.login("alice", &Secret::from("sesame")).unwrap();
+
+    assert_eq!(auth.get_token().unwrap().expose(), "token");
+    assert_eq!(auth.user_id(), Some("4f2a"));
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with("POST /jellyfin/Users/AuthenticateByName HTTP/1.1\r\n"));
+    assert!(requests[0].contains("Authorization: MediaBrowser Client=\"music_streamer\", \
+                                  Device=\"Living room\", DeviceId=\"device-1\", Version=\""));
+    assert!(requests[0].ends_with(r#"{"Pw":"sesame","Username":"alice"}"#));
+}
+
+#[test]
+fn verify_sends_token_and_finds_user() {
+    let server = StandIn::start(vec![r#"{"Id":"4f2a","Name":"alice"}"#]);
+    let mut auth = authenticator(&server);
+    auth.save_token(Secret::from("token")).unwrap();
+    auth.verify().unwrap();
+    assert_eq!(auth.user_id(), Some("4f2a"));
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with("GET /jellyfin/Users/Me HTTP/1.1\r\n"));
+    assert!(requests[0].contains("DeviceId=\"device-1\""));
+    assert!(requests[0].contains(", Token=\"token\"\r\n"));
+}
@@ -0,0 +1,98 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate crypto;
+extern crate music_streamer;
+
+mod common;
+
+use common::StandIn;
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use music_streamer::auth::lastfm::AuthLastFm;
+use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Secret};
+use music_streamer::auth::{ServiceConfig, ServiceType};
+use music_streamer::http::HyperTransport;
+
+fn authenticator(server: &StandIn) -> AuthLastFm {
+    let api_url = server.url("/2.0/");
+    let config = ServiceConfig::new(ServiceType::LastFm)
+        .with_token_url(&api_url)
+        .with_api_url(&api_url);
+    AuthLastFm::with_config(config, Box::new(HyperTransport::new()))
+}
+
+fn md5(text: &str) -> String {
+    let mut md5 = Md5::new();
+    md5.input_str(text);
+    md5.result_str()
+}
+
+#[test]
+fn desktop_flow_sends_signed_calls() {
+    let server = StandIn::start(vec![
+        r#"{"token":"request-token"}"#,
+        r#"{"session":{"name":"alice","key":"session-key","subscriber":0}}"#,
+    ]);
+    let mut auth = authenticator(&server);
+    let secret = Secret::from("secret");
+
+    let token = auth.request_token("key", &secret).unwrap();
+    auth.authenticate_application("key", &secret, &token).unwrap();
+    assert_eq!(auth.get_token().unwrap().expose(), "session-key");
+    assert_eq!(auth.username(), Some("alice"));
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with(&format!(
+        "GET /2.0/?api_key=key&method=auth.getToken&api_sig={}&format=json HTTP/1.1\r\n",
+        md5("api_keykeymethodauth.getTokensecret"))));
+    assert!(requests[1].starts_with(&format!(
+        "GET /2.0/?api_key=key&method=auth.getSession&token=request-token&api_sig={}\
+         &format=json HTTP/1.1\r\n",
+        md5("api_keykeymethodauth.getSessiontokenrequest-tokensecret"))));
+}
+
+#[test]
+fn desktop_flow_refuses_other_token() {
+    let server = StandIn::start(vec![r#"{"token":"request-token"}"#]);
+    let mut auth = authenticator(&server);
+    let secret = Secret::from("secret");
+
+    auth.request_token("key", &secret).unwrap();
+    match auth.parse_response_code("http://example.com/cb?token=other") {
+        Err(Error::StateMismatch) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    match auth.authenticate_application("key", &secret, &Secret::from("other")) {
+        Err(Error::StateMismatch) => {}
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[test]
+fn refused_session_key_grants_nothing() {
+    let server = StandIn::start(vec![]);
+    let mut auth = authenticator(&server);
+    auth.save_token(Secret::from("key")).unwrap();
+    auth.revoke().unwrap();
+    assert_eq!(auth.status(), AuthorizationStatus::Revoked);
+
+    assert!(auth.save_token(Secret::from("other")).is_err());
+    assert!(auth.get_token().is_err());
+    assert!(auth.granted_permissions().is_empty());
+    assert!(server.requests().is_empty());
+}
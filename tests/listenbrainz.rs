@@ -0,0 +1,74 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate music_streamer;
+
+mod common;
+
+use common::StandIn;
+use music_streamer::auth::listenbrainz::AuthListenBrainz;
+use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+use music_streamer::auth::store::MemoryStore;
+use music_streamer::http::HyperTransport;
+
+fn authenticator(server: &StandIn) -> AuthListenBrainz {
+    let config = ServiceConfig::new(ServiceType::ListenBrainz)
+        .with_api_url(&server.url("/"));
+    AuthListenBrainz::with_config(config, Box::new(HyperTransport::new()))
+}
+
+#[test]
+fn login_sends_token_header() {
+    let server = StandIn::start(vec![
+        r#"{"code":200,"message":"Token valid.","valid":true,"user_name":"alice"}"#,
+    ]);
+    let mut auth = authenticator(&server);
+    auth.login_with_key(&Secret::from("user-token")).unwrap();
+
+    assert_eq!(auth.username(), Some("alice"));
+    assert_eq!(auth.get_token().unwrap().expose(), "user-token");
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with("GET /1/validate-token HTTP/1.1\r\n"));
+    assert!(requests[0].contains("Authorization: Token user-token\r\n"));
+}
+
+#[test]
+fn password_login_is_refused() {
+    let server = StandIn::start(vec![]);
+    let mut auth = authenticator(&server);
+    assert!(auth.login("alice", &Secret::from("sesame")).is_err());
+    assert_eq!(auth.status(), AuthorizationStatus::Nothing);
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn store_and_snapshot_restore_alike() {
+    let store = MemoryStore::new();
+    let mut auth = AuthListenBrainz::new();
+    auth.attach_store(Box::new(store.clone()), "alice").unwrap();
+    auth.save_token(Secret::from("user-token")).unwrap();
+    let snapshot = auth.export_snapshot(true);
+
+    let mut from_store = AuthListenBrainz::new();
+    assert!(from_store.attach_store(Box::new(store), "alice").unwrap());
+    let mut from_snapshot = AuthListenBrainz::new();
+    from_snapshot.import_snapshot(&snapshot).unwrap();
+
+    assert_eq!(from_store.status(), AuthorizationStatus::TokenAcquired);
+    assert_eq!(from_snapshot.status(), AuthorizationStatus::TokenAcquired);
+}
@@ -0,0 +1,51 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate music_streamer;
+
+use music_streamer::auth::soundcloud;
+use music_streamer::auth::{Authenticator, AuthorizationStatus, Permission, Secret};
+use music_streamer::auth::{ServiceConfig, ServiceType};
+use music_streamer::http::{MockTransport, Response};
+
+#[test]
+fn client_token_drops_refresh_token_of_user() {
+    let mock = MockTransport::new();
+    mock.push_response(Response::new(200, r#"{"access_token":"user","expires_in":3599,
+        "refresh_token":"refresh","scope":""}"#));
+    mock.push_response(Response::new(200, r#"{"access_token":"app","expires_in":3599}"#));
+    let config = ServiceConfig::new(ServiceType::SoundCloud)
+        .with_token_url("http://127.0.0.1:8080/oauth/token");
+    let mut auth = soundcloud::with_config(config, Box::new(mock.clone()));
+
+    let link = auth.get_authorize_link("111", "http://localhost/cb",
+                                       &[Permission::BasicAccess, Permission::OfflineAccess])
+        .unwrap();
+    let state = link.split("&state=").nth(1).unwrap();
+    let code = auth.parse_response_code(&format!("http://localhost/cb?code=c&state={}", state))
+        .unwrap();
+    auth.authenticate_application("111", &Secret::from("secret"), &code).unwrap();
+    assert_eq!(auth.refresh_token().unwrap().expose(), "refresh");
+
+    auth.authenticate_client("111", &Secret::from("secret")).unwrap();
+
+    assert_eq!(auth.get_token().unwrap().expose(), "app");
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+    assert!(auth.refresh_token().is_none());
+    assert!(auth.granted_permissions().is_empty());
+    assert!(auth.export_snapshot(true).refresh_token.is_none());
+    assert!(auth.refresh("111", &Secret::from("secret")).is_err());
+    assert_eq!(mock.requests().len(), 2);
+}
@@ -0,0 +1,62 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate music_streamer;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use music_streamer::auth::deezer::AuthDeezer;
+use music_streamer::auth::redirect::RedirectListener;
+use music_streamer::auth::{Authenticator, Permission};
+
+/// Send `GET` request and return the status line of the answer
+fn get(port: u16, target: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n", target).unwrap();
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+    answer.lines().next().unwrap_or("").to_string()
+}
+
+#[test]
+fn silent_and_unrelated_connections_do_not_block_the_redirect() {
+    let listener = RedirectListener::bind().unwrap();
+    let port = listener.port().unwrap();
+    let mut auth = AuthDeezer::new();
+    let link = auth.get_authorize_link("111", &listener.redirect_uri().unwrap(),
+                                       &[Permission::BasicAccess])
+        .unwrap();
+    let state = link.split("&state=").nth(1).unwrap().to_string();
+
+    let browser = thread::spawn(move || {
+        // speculative connection which never sends anything
+        let preconnect = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        // connection reset before it is answered
+        drop(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        let favicon = get(port, "/favicon.ico");
+        let redirect = get(port, &format!("/?code=c&state={}", state));
+        drop(preconnect);
+        (favicon, redirect)
+    });
+
+    let code = listener.wait(&mut auth, Duration::from_secs(30)).unwrap();
+    assert_eq!(code.expose(), "c");
+    let (favicon, redirect) = browser.join().unwrap();
+    assert_eq!(favicon, "HTTP/1.1 404 Not Found");
+    assert_eq!(redirect, "HTTP/1.1 200 OK");
+}
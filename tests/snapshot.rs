@@ -0,0 +1,52 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate music_streamer;
+
+use music_streamer::auth::deezer::AuthDeezer;
+use music_streamer::auth::store::{StoredToken, TokenStore};
+use music_streamer::auth::{Authenticator, AuthorizationStatus, Error, Secret, ServiceType};
+
+/// Store which has nothing saved and can't save anything
+struct ReadOnlyStore;
+
+impl TokenStore for ReadOnlyStore {
+    fn save(&self, _: ServiceType, _: &str, _: &StoredToken) -> Result<(), Error> {
+        Err(Error::Storage("store is read only".to_string()))
+    }
+
+    fn load(&self, _: ServiceType, _: &str) -> Result<Option<StoredToken>, Error> {
+        Ok(None)
+    }
+
+    fn delete(&self, _: ServiceType, _: &str) -> Result<(), Error> {
+        Err(Error::Storage("store is read only".to_string()))
+    }
+}
+
+#[test]
+fn failed_import_keeps_the_session() {
+    let mut other = AuthDeezer::new();
+    other.save_token(Secret::from("new")).unwrap();
+    let snapshot = other.export_snapshot(true);
+
+    let mut auth = AuthDeezer::new();
+    auth.save_token(Secret::from("old")).unwrap();
+    assert!(!auth.attach_store(Box::new(ReadOnlyStore), "user").unwrap());
+
+    assert!(auth.import_snapshot(&snapshot).is_err());
+    assert_eq!(auth.get_token().unwrap().expose(), "old");
+    assert_eq!(auth.status(), AuthorizationStatus::TokenAcquired);
+}
@@ -0,0 +1,87 @@
+// This file is part of libmusic_streamer.
+//
+// libmusic_streamer is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// libmusic_streamer is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with libmusic_streamer.  If not, see <http://www.gnu.org/licenses/>.
+
+extern crate crypto;
+extern crate music_streamer;
+
+mod common;
+
+use common::StandIn;
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use music_streamer::auth::subsonic::{AuthMode, AuthSubsonic};
+use music_streamer::auth::{Authenticator, AuthorizationStatus, CredentialAuthenticator};
+use music_streamer::auth::{Secret, ServiceConfig, ServiceType};
+use music_streamer::http::HyperTransport;
+
+const OK: &str = r#"{"subsonic-response":{"status":"ok","version":"1.16.1"}}"#;
+
+fn authenticator(server: &StandIn) -> AuthSubsonic {
+    let config = ServiceConfig::new(ServiceType::Subsonic)
+        .with_api_url(&server.url("/navidrome"));
+    AuthSubsonic::with_config(config, Box::new(HyperTransport::new()))
+}
+
+/// Value of the query parameter in the request line
+fn param(request: &str, name: &str) -> String {
+    let target = request.split_whitespace().nth(1).unwrap();
+    target.split(['?', '&'])
+        .find(|param| param.starts_with(&format!("{}=", name)))
+        .map(|param| param[name.len() + 1..].to_string())
+        .unwrap()
+}
+
+#[test]
+fn login_sends_salted_token() {
+    let server = StandIn::start(vec![OK]);
+    let mut auth = authenticator(&server);
+    auth.login("alice", &Secret::from("sesame")).unwrap();
+    assert_eq!(auth.status(), AuthorizationStatus::AuthorizationCompleted);
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with("GET /navidrome/rest/ping.view?u=alice&t="));
+    assert!(requests[0].contains("&v=1.16.1&c=music_streamer&f=json HTTP/1.1\r\n"));
+    assert!(!requests[0].contains("sesame"));
+
+    let mut md5 = Md5::new();
+    md5.input_str(&format!("sesame{}", param(&requests[0], "s")));
+    assert_eq!(param(&requests[0], "t"), md5.result_str());
+}
+
+#[test]
+fn token_is_reused_until_next_login() {
+    let server = StandIn::start(vec![OK, OK, OK]);
+    let mut auth = authenticator(&server);
+    auth.login("alice", &Secret::from("sesame")).unwrap();
+    auth.verify().unwrap();
+    auth.login("alice", &Secret::from("sesame")).unwrap();
+
+    let requests = server.requests();
+    assert_eq!(param(&requests[0], "s"), param(&requests[1], "s"));
+    assert_ne!(param(&requests[1], "s"), param(&requests[2], "s"));
+}
+
+#[test]
+fn password_mode_sends_encoded_password() {
+    let server = StandIn::start(vec![OK]);
+    let mut auth = authenticator(&server);
+    auth.set_mode(AuthMode::Password);
+    auth.login("alice", &Secret::from("sesame")).unwrap();
+
+    let requests = server.requests();
+    assert!(requests[0].starts_with("GET /navidrome/rest/ping.view?u=alice\
+                                     &p=enc%3A736573616d65&v=1.16.1"));
+    assert!(auth.export_snapshot(true).token.is_none());
+}